@@ -9,21 +9,37 @@
 
 #![allow(dead_code)]
 
-use std::{collections::BTreeMap, path::Path};
+use std::{collections::BTreeSet, io::{Read, Write}, path::Path};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
-use crate::tuning::{Interval, IntervalDirection, IntervalStep, Tuning};
+use crate::tuning::{Interval, IntervalDirection, IntervalStep, MidiNote, Tuning};
 
+pub use self::analyze::Analysis;
+pub use self::coverage::{Coverage, PitchCoverage};
+pub use self::coord::{Hex, Offset, ALL_DIRS};
+pub use self::region::Region;
+pub use self::search::{search, Candidate};
+pub use self::settings::{Curve, GlobalSettings, VelocityIntervals, CURVE_LEN, VELOCITY_INTERVALS_LEN};
+pub use self::fill::{Conflict, Dropped, FillResult};
+pub use self::ltn::{Ltn, LtnKey, ParseError, ParseMode};
 use self::fill::Filler;
+pub use self::validate::Validation;
 
+mod analyze;
+mod coord;
+mod coverage;
 mod ltn;
+mod region;
+mod search;
+mod settings;
 mod svg;
 mod fill;
+mod validate;
 
 /// The lumatone itself represents the keys by a pair of numbers, the group, a
 /// number between 0 and 4, and the key itself, a number between 0 and 56.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct KeyIndex {
     /// Group across the keyboard.
     pub group: u8,
@@ -37,15 +53,6 @@ impl KeyIndex {
     }
 }
 
-/// A delta move.  Indicates A movement.
-#[derive(Debug, Copy, Clone)]
-struct KeyMove {
-    pub group: i8,
-    pub key: u8,
-}
-
-struct MoveMap(BTreeMap<Dir, Vec<Option<KeyMove>>>);
-
 /// Each key has the following information associated with it.
 #[derive(Debug, Default, Clone)]
 pub struct KeyInfo {
@@ -57,16 +64,96 @@ pub struct KeyInfo {
     pub color: RGB8,
     /// A label to print on the key.
     pub label: String,
+    /// What the key sends.
+    pub key_type: KeyType,
+    /// For controller keys, send the highest value when the key is up, and
+    /// the lowest when it is pressed.
+    pub cc_invert: bool,
+}
+
+/// What a key sends when it is pressed.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum KeyType {
+    /// Note on and note off.
+    #[default]
+    Note,
+    /// A continuous controller, with the controller number in place of the
+    /// note, such as 64 for a sustain pedal.
+    Controller,
+    /// Note on and note off, with polyphonic aftertouch.
+    LumaTouch,
+    /// Nothing.
+    Disabled,
+}
+
+impl KeyType {
+    /// Decode the `KTyp` value from an LTN file.
+    pub fn from_ltn(value: u8) -> Option<KeyType> {
+        match value {
+            0 | 4 => Some(KeyType::Disabled),
+            1 => Some(KeyType::Note),
+            2 => Some(KeyType::Controller),
+            3 => Some(KeyType::LumaTouch),
+            _ => None,
+        }
+    }
+
+    /// The `KTyp` value for an LTN file.
+    pub fn ltn(self) -> u8 {
+        match self {
+            KeyType::Note => 1,
+            KeyType::Controller => 2,
+            KeyType::LumaTouch => 3,
+            KeyType::Disabled => 4,
+        }
+    }
+
+    /// Does this key play a note.
+    pub fn is_note(self) -> bool {
+        matches!(self, KeyType::Note | KeyType::LumaTouch)
+    }
+}
+
+impl KeyInfo {
+    /// A key that sends the given continuous controller.
+    pub fn controller(channel: u8, number: u8) -> KeyInfo {
+        KeyInfo {
+            channel,
+            note: number,
+            color: RGB8::white(),
+            label: format!("CC{}", number),
+            key_type: KeyType::Controller,
+            cc_invert: false,
+        }
+    }
+
+    /// A key that doesn't send anything.  The channel is 1, as the editor
+    /// writes disabled keys.
+    pub fn disabled() -> KeyInfo {
+        KeyInfo {
+            channel: 1,
+            color: RGB8::new(0, 0, 0),
+            key_type: KeyType::Disabled,
+            ..KeyInfo::default()
+        }
+    }
+
+    /// Does this key play a note.
+    pub fn is_note(&self) -> bool {
+        self.key_type.is_note()
+    }
 }
 
 /// The entire keyboard.
 #[derive(Debug, Clone)]
 pub struct Keyboard {
     pub keys: [[Option<KeyInfo>; 56]; 5],
+    /// The settings for the whole keyboard.
+    pub settings: GlobalSettings,
 }
 
 /// For now, just use a local RGB8.  This should match other definitions.
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
 pub struct RGB8 {
     pub r: u8,
     pub g: u8,
@@ -117,59 +204,46 @@ impl Default for Keyboard {
             // As of rust 1.78, Default is only implemented for arrays up to 32.
             // keys: Default::default(),
             keys: [a, b, c, d, e],
+            settings: GlobalSettings::default(),
         }
     }
 }
 
 impl Keyboard {
     pub fn write_svg<P: AsRef<Path>>(&self, p: P) -> Result<()> {
-        let mut writer = svg::SvgOut::new();
-        let mv = MoveMap::make();
+        self.svg().save(p)
+    }
 
-        let mut row_start = KeyIndex::origin();
-        let mut last_x0 = 0;
-
-        for (y, &(x0, xlen)) in SIZES.iter().enumerate() {
-            // Move to the new position.
-            if y > 0 {
-                // Move to the right before down, so we always stay within the
-                // keyboard.
-                while x0 > last_x0 {
-                    row_start = mv.trymove(row_start, Dir::Right).unwrap();
-                    last_x0 += 1;
-                }
+    /// Write the SVG image of this keyboard to the given writer.
+    pub fn write_svg_to<W: Write>(&self, writer: W) -> Result<()> {
+        self.svg().write(writer)
+    }
 
-                // Now move down.
-                let dir = if (y & 1) == 1 {
-                    Dir::DownRight
-                } else {
-                    Dir::DownLeft
-                };
-                row_start = mv.trymove(row_start, dir)
-                    .unwrap();
-                // println!("Move {:?} to {:?}", dir, row_start);
-            }
+    /// The SVG image of this keyboard, as text.
+    pub fn svg_string(&self) -> String {
+        self.svg().text()
+    }
+
+    fn svg(&self) -> svg::SvgOut {
+        let mut writer = svg::SvgOut::new();
 
-            let mut key = row_start.clone();
-            for x in x0..x0 + xlen {
-                if x > x0 {
-                    // If this fails, our table of positions is wrong.
-                    // println!("At {:?} move right", key);
-                    key = mv.trymove(key, Dir::Right).unwrap();
+        // Draw the keys a row at a time, from left to right.
+        let mut keys: Vec<(Offset, KeyIndex)> = KeyIndex::iter_all()
+            .filter_map(|key| Some((key.offset()?, key)))
+            .collect();
+        keys.sort_by_key(|&(offset, _)| offset);
+        for (Offset { row, col }, key) in keys {
+            match self.get(key) {
+                Some(info) => {
+                    writer.add(col as u32, row as u32, info.color, &info.label);
                 }
-                match self.get(key) {
-                    Some(info) => {
-                        // let label = format!("{},{}", key.group, key.key);
-                        writer.add(x, y as u32, info.color, &info.label);
-                    }
-                    None => {
-                        writer.add(x, y as u32, RGB8::white(), "");
-                    }
+                None => {
+                    writer.add(col as u32, row as u32, RGB8::white(), "");
                 }
             }
         }
 
-        writer.save(p)
+        writer
     }
 
     pub fn get(&self, index: KeyIndex) -> Option<&KeyInfo> {
@@ -190,6 +264,23 @@ impl Keyboard {
     pub fn set(&mut self, index: KeyIndex, info: Option<KeyInfo>) {
         self.keys[index.group as usize][index.key as usize] = info;
     }
+
+    /// Reserve a key for something other than a note, such as a controller.
+    /// Fills pass over keys that don't play notes, so they can be reserved
+    /// before the layout is filled in.
+    pub fn reserve(&mut self, index: KeyIndex, info: KeyInfo) {
+        self.set(index, Some(info));
+    }
+
+    /// Return the midi channels used by the keys that play notes, in order.
+    pub fn channels(&self) -> Vec<u8> {
+        let channels: BTreeSet<u8> = KeyIndex::iter_all()
+            .filter_map(|index| self.get(index))
+            .filter(|info| info.is_note())
+            .map(|info| info.channel)
+            .collect();
+        channels.into_iter().collect()
+    }
 }
 
 // A single section of the lumatone keyboard is layed out like this (without the
@@ -213,200 +304,42 @@ pub enum Dir {
     UpLeft, UpRight, Right, DownRight, DownLeft, Left,
 }
 
-impl MoveMap {
-    /// Construct the key movement mapping.
-    fn make() -> MoveMap {
-        let mut result = BTreeMap::new();
+impl Keyboard {
+    /// Attempt to load a keyboard from a .ltn file.  Problems with the file
+    /// are ignored, as in lenient mode.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keyboard> {
+        Ok(ltn::load(path, ParseMode::Lenient)?.0)
+    }
+
+    /// Attempt to load a keyboard from a .ltn file.  Any problem with the
+    /// file is an error.
+    pub fn load_strict<P: AsRef<Path>>(path: P) -> Result<Keyboard> {
+        Ok(ltn::load(path, ParseMode::Strict)?.0)
+    }
 
-        // Movement to the right.
-        let mut right = Vec::with_capacity(56);
-        for i in 0..56 {
-            right.push(Some(KeyMove {group: 0, key: i + 1}));
-        }
-        // Two of the keys have nothing to the right.
-        right[1] = None;
-        right[6] = None;
-        // And several of the keys move to another group.
-        right[12] = Some(KeyMove { group: 1, key: 0 });
-        right[18] = Some(KeyMove { group: 1, key: 2 });
-        right[24] = Some(KeyMove { group: 1, key: 7 });
-        right[30] = Some(KeyMove { group: 1, key: 13 });
-        right[36] = Some(KeyMove { group: 1, key: 19 });
-        right[42] = Some(KeyMove { group: 1, key: 25 });
-        right[48] = Some(KeyMove { group: 1, key: 31 });
-        right[53] = Some(KeyMove { group: 1, key: 37 });
-        right[55] = Some(KeyMove { group: 1, key: 43 });
-
-        result.insert(Dir::Right, right);
-
-        // Movement to the left.
-        let mut left = Vec::with_capacity(56);
-        left.push(None);
-        for i in 1..56 {
-            left.push(Some(KeyMove {group: 0, key: i - 1}));
-        }
-        // Keys with nothing to the left.
-        left[49] = None;
-        left[54] = None;
-        // And the ones that move to a new group.
-        left[0] = Some(KeyMove { group: -1, key: 12 });
-        left[2] = Some(KeyMove { group: -1, key: 18 });
-        left[7] = Some(KeyMove { group: -1, key: 24 });
-        left[13] = Some(KeyMove { group: -1, key: 30 });
-        left[19] = Some(KeyMove { group: -1, key: 36 });
-        left[25] = Some(KeyMove { group: -1, key: 42 });
-        left[31] = Some(KeyMove { group: -1, key: 48 });
-        left[37] = Some(KeyMove { group: -1, key: 53 });
-        left[43] = Some(KeyMove { group: -1, key: 55 });
-
-        result.insert(Dir::Left, left);
-
-        // Movement down and right.
-        let mut dr: Vec<_> = [
-            /* 00 */ 2, 3,
-            /* 02 */ 8, 9, 10, 11, 12,
-            /* 07 */ 13, 14, 15, 16, 17, 18,
-            /* 13 */ 20, 21, 22, 23, 24, 7,
-            /* 19 */ 25, 26, 27, 28, 29, 30,
-            /* 25 */ 32, 33, 34, 35, 36, 19,
-            /* 31 */ 37, 38, 39, 40, 41, 42,
-            /* 37 */ 44, 45, 46, 47, 48, 31,
-            /* 43 */ 0, 49, 50, 51, 52, 53,
-            /* 49 */ 0, 0, 54, 55, 43,
-            /* 54 */ 0, 0,
-        ].iter().map(|&n| Some(KeyMove { group: 0, key: n })).collect();
-        // Keys with no movement DR.
-        dr[43] = None;
-        dr[49] = None;
-        dr[50] = None;
-        dr[54] = None;
-        dr[55] = None;
-        // Moves to next group.
-        dr[18].as_mut().map(|k| k.group = 1);
-        dr[30].as_mut().map(|k| k.group = 1);
-        dr[42].as_mut().map(|k| k.group = 1);
-        dr[53].as_mut().map(|k| k.group = 1);
-
-        result.insert(Dir::DownRight, dr);
-
-        // Movement up and left.
-        let mut ul: Vec<_> = [
-            /* 00 */ 0, 0,
-            /* 02 */ 0, 1, 0, 0, 0,
-            /* 07 */ 18, 2, 3, 4, 5, 6,
-            /* 13 */ 7, 8, 9, 10, 11, 12,
-            /* 19 */ 30, 13, 14, 15, 16, 17,
-            /* 25 */ 19, 20, 21, 22, 23, 24,
-            /* 31 */ 42, 25, 26, 27, 28, 29,
-            /* 37 */ 31, 32, 33, 34, 35, 36,
-            /* 43 */ 53, 37, 38, 39, 40, 41,
-            /* 49 */ 44, 45, 46, 47, 48,
-            /* 54 */ 51, 52,
-        ].iter().map(|&n| Some(KeyMove { group: 0, key: n })).collect();
-        // No movement UL
-        ul[0] = None;
-        ul[1] = None;
-        ul[4] = None;
-        ul[5] = None;
-        ul[6] = None;
-        // Moves to previous group.
-        ul[7].as_mut().map(|k| k.group = -1);
-        ul[19].as_mut().map(|k| k.group = -1);
-        ul[31].as_mut().map(|k| k.group = -1);
-        ul[43].as_mut().map(|k| k.group = -1);
-
-        result.insert(Dir::UpLeft, ul);
-
-        // Movement down and left.
-        let mut dl: Vec<_> = [
-            /* 00 */ 18, 2,
-            /* 02 */ 7, 8, 9, 10, 11,
-            /* 07 */ 30, 13, 14, 15, 16, 17,
-            /* 13 */ 19, 20, 21, 22, 23, 24,
-            /* 19 */ 42, 25, 26, 27, 28, 29,
-            /* 25 */ 31, 32, 33, 34, 35, 36,
-            /* 31 */ 53, 37, 38, 39, 40, 41,
-            /* 37 */ 43, 44, 45, 46, 47, 48,
-            /* 43 */ 0, 0, 49, 50, 51, 52,
-            /* 49 */ 0, 0, 0, 54, 55,
-            /* 54 */ 0, 0,
-        ].iter().map(|&n| Some(KeyMove { group: 0, key: n })).collect();
-        // Keys with no movement DR.
-        dl[43] = None;
-        dl[44] = None;
-        dl[49] = None;
-        dl[50] = None;
-        dl[51] = None;
-        dl[54] = None;
-        dl[55] = None;
-        // Moves to next group.
-        dl[0].as_mut().map(|k| k.group = -1);
-        dl[7].as_mut().map(|k| k.group = -1);
-        dl[19].as_mut().map(|k| k.group = -1);
-        dl[31].as_mut().map(|k| k.group = -1);
-
-        result.insert(Dir::DownLeft, dl);
-
-        // Movement up and right.
-        let mut ur: Vec<_> = [
-            /* 00 */ 0, 0,
-            /* 02 */ 1, 0, 0, 0, 0,
-            /* 07 */ 2, 3, 4, 5, 6, 0,
-            /* 13 */ 8, 9, 10, 11, 12, 0,
-            /* 19 */ 13, 14, 15, 16, 17, 18,
-            /* 25 */ 20, 21, 22, 23, 24, 7,
-            /* 31 */ 25, 26, 27, 28, 29, 30,
-            /* 37 */ 32, 33, 34, 35, 36, 19,
-            /* 43 */ 37, 38, 39, 40, 41, 42,
-            /* 49 */ 45, 46, 47, 48, 31,
-            /* 54 */ 52, 53,
-        ].iter().map(|&n| Some(KeyMove { group: 0, key: n })).collect();
-        // No movement UL
-        ur[0] = None;
-        ur[1] = None;
-        ur[3] = None;
-        ur[4] = None;
-        ur[5] = None;
-        ur[6] = None;
-        ur[12] = None;
-        // Moves to next group.
-        ur[18].as_mut().map(|k| k.group = 1);
-        ur[30].as_mut().map(|k| k.group = 1);
-        ur[42].as_mut().map(|k| k.group = 1);
-        ur[53].as_mut().map(|k| k.group = 1);
-
-        result.insert(Dir::UpRight, ur);
-
-
-        MoveMap(result)
-    }
-
-    // Return the new key from this movement.
-    fn trymove(&self, key: KeyIndex, dir: Dir) -> Option<KeyIndex> {
-        // self.0.get(&dir).and_then(|moves| moves.get(key.key as usize))
-        self.0
-            .get(&dir)
-            .and_then(|moves| moves[key.key as usize])
-            .and_then(|mv| {
-                // Check if we move off of the edge.
-                if (mv.group < 0 && key.group == 0) ||
-                    (mv.group > 0 && key.group == 4)
-                {
-                    None
-                } else {
-                    Some(KeyIndex {
-                        group: (key.group as i8 + mv.group) as u8,
-                        key: mv.key,
-                    })
-                }
-            })
+    /// Load a keyboard from a .ltn file, returning the problems found as
+    /// warnings in lenient mode.
+    pub fn load_with<P: AsRef<Path>>(path: P, mode: ParseMode) -> Result<(Keyboard, Vec<ParseError>)> {
+        ltn::load(path, mode)
     }
-}
 
-impl Keyboard {
-    /// Attempt to load a keyboard from a .ltn file.
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keyboard> {
-        ltn::load(path)
+    /// Read a keyboard in the .ltn format.  Problems are ignored, as in
+    /// lenient mode.
+    pub fn read_ltn<R: Read>(reader: R) -> Result<Keyboard> {
+        Ok(Keyboard::read_ltn_with(reader, ParseMode::Lenient)?.0)
+    }
+
+    /// Read a keyboard in the .ltn format, returning the problems found as
+    /// warnings in lenient mode.
+    pub fn read_ltn_with<R: Read>(reader: R, mode: ParseMode) -> Result<(Keyboard, Vec<ParseError>)> {
+        let (ltn, warnings) = Ltn::read_with(reader, mode)?;
+        Ok((ltn.keyboard()?, warnings))
+    }
+
+    /// Parse the text of a .ltn file.  Problems are ignored, as in lenient
+    /// mode.
+    pub fn parse_ltn(text: &str) -> Result<Keyboard> {
+        Ltn::parse(text)?.keyboard()
     }
 
     /// Write this keyboard out to a LTN file.
@@ -414,6 +347,16 @@ impl Keyboard {
         ltn::save(path, self)
     }
 
+    /// Write this keyboard in the .ltn format to the given writer.
+    pub fn write_ltn_to<W: Write>(&self, writer: W) -> Result<()> {
+        Ltn::from_keyboard(self).write(writer)
+    }
+
+    /// This keyboard in the .ltn format, as text.
+    pub fn ltn_string(&self) -> String {
+        Ltn::from_keyboard(self).to_string()
+    }
+
     /// Fill in this keyboard, with a Lumatone reference chart.  The labels give
     /// the key number and the colors indicate the 5 sections.
     pub fn fill_reference(&mut self) {
@@ -424,6 +367,7 @@ impl Keyboard {
                 note: 0,
                 color: SECTIONS[key.group as usize],
                 label,
+                ..KeyInfo::default()
             }));
         }
     }
@@ -432,7 +376,8 @@ impl Keyboard {
     /// generators, just to make it simple, but the keyboard only works if two
     /// of the generators define the third.  As long as they are actually
     /// generators, and the size is sufficient, this should fill in the keyboard
-    /// properly.
+    /// properly.  The result gives the keys that this fill filled in, along
+    /// with where it ran into earlier fills, or couldn't place a note.
     pub fn fill_layout(
         &mut self,
         // The tuning system involved.
@@ -441,9 +386,12 @@ impl Keyboard {
         layout: &Layout,
         // The description of what to fill in.
         info: &FillInfo,
-    ) {
-        let mut filler = Filler::new(self, tuning, layout, info);
-        filler.run();
+    ) -> Result<FillResult> {
+        let Some(anchor) = info.anchor.resolve(tuning) else {
+            bail!("Anchor {:?} isn't a note of the tuning", info.anchor);
+        };
+        let mut filler = Filler::new(self, tuning, layout, info, anchor);
+        Ok(filler.run())
     }
 }
 
@@ -457,6 +405,15 @@ pub struct Layout {
     up_right: Interval,
 }
 
+impl Layout {
+    /// Construct a layout from the intervals along each axis.  Besides the
+    /// named intervals, the axes can use raw steps or vectors of fifths and
+    /// octaves, such as a neutral third in 24-EDO.
+    pub const fn new(right: Interval, up_left: Interval, up_right: Interval) -> Layout {
+        Layout { right, up_left, up_right }
+    }
+}
+
 pub static WICKI_HAYDEN: Layout = Layout {
     right: Interval::new(IntervalStep::MajorSecond, IntervalDirection::Up),
     up_left: Interval::new(IntervalStep::PerfectFourth, IntervalDirection::Up),
@@ -476,23 +433,324 @@ pub static BOSANQUET: Layout = Layout {
 };
 
 /// Parameters needed to fill a layout.
-pub struct FillInfo {
-    // How many places to move to the left.
-    pub left: usize,
-    // How many places to move to the right.
-    pub right: usize,
-    // Starting cell, this will generally be middle C.
+pub struct FillInfo<'a> {
+    // Starting cell, which holds the anchor note.
     pub start: KeyIndex,
+    // The note to place on the starting cell, generally middle C.
+    pub anchor: Anchor<'a>,
+    // The keys that can be filled.  Only keys in the region that can be
+    // reached from the start, without leaving the region, are filled.
+    pub region: Region<'a>,
+}
+
+impl<'a> FillInfo<'a> {
+    /// Fill the region with middle C on the starting key.
+    pub const fn new(start: KeyIndex, region: Region<'a>) -> FillInfo<'a> {
+        FillInfo { start, anchor: Anchor::MiddleC, region }
+    }
+
+    /// Place a different note on the starting key.
+    pub const fn with_anchor(self, anchor: Anchor<'a>) -> FillInfo<'a> {
+        FillInfo { anchor, ..self }
+    }
+}
+
+/// The note placed on the starting key of a fill.
+#[derive(Debug, Default, Copy, Clone)]
+pub enum Anchor<'a> {
+    /// Middle C of the tuning.
+    #[default]
+    MiddleC,
+    /// The note with this name, such as "G2", as named by the tuning.
+    Name(&'a str),
+    /// The note this many steps from middle C.
+    Steps(isize),
+    /// A particular midi note.
+    Note(MidiNote),
+}
+
+impl Anchor<'_> {
+    /// Find the anchor note in this tuning.  Returns None if the tuning
+    /// doesn't have the note.
+    pub fn resolve(&self, tuning: &dyn Tuning) -> Option<MidiNote> {
+        match *self {
+            Anchor::MiddleC => Some(tuning.middle_c()),
+            Anchor::Name(name) => tuning.note_named(name),
+            Anchor::Steps(steps) => {
+                let dir = if steps < 0 { IntervalDirection::Down } else { IntervalDirection::Up };
+                tuning.interval(tuning.middle_c(), Interval::steps(steps.abs(), dir))
+            }
+            Anchor::Note(note) => tuning.cents(note).map(|_| note),
+        }
+    }
+}
+
+impl KeyIndex {
+    fn iter_all() -> KeyIndexIter {
+        KeyIndexIter {
+            group: 0,
+            key: 0,
+        }
+    }
+}
+
+struct KeyIndexIter {
+    group: u8,
+    key: u8,
+}
+
+impl Iterator for KeyIndexIter {
+    type Item = KeyIndex;
+    fn next(&mut self) -> Option<KeyIndex> {
+        if self.group >= 5 {
+            return None;
+        }
+        let result = KeyIndex { group: self.group, key: self.key };
+        if self.key == 55 {
+            self.key = 0;
+            self.group += 1;
+        } else {
+            self.key += 1;
+        }
+        Some(result)
+    }
+}
+
+/// Some colors for the sections.
+static SECTIONS: [RGB8; 5] = [
+    RGB8::new(204, 61, 61).lighten(),  // A pastel red
+    RGB8::new(175, 204, 61).lighten(), // A pastel lime green
+    RGB8::new(61, 204, 118).lighten(), // A pastel turquoise
+    RGB8::new(61, 118, 204).lighten(), // A pastel blue
+    RGB8::new(175, 61, 204).lighten(), // A pastel purple
+    ];
+
+/// A keyboard with a single fill of the layout, with middle C on the start
+/// key, for the tests.
+#[cfg(test)]
+pub(crate) fn filled(tuning: &dyn Tuning, layout: &Layout, start: KeyIndex, region: Region) -> Keyboard {
+    let mut keyb = Keyboard::default();
+    keyb.fill_layout(tuning, layout, &FillInfo::new(start, region)).unwrap();
+    keyb
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeMap;
+
+    use super::Anchor;
+    use super::filled;
     use super::Dir;
+    use super::FillInfo;
     use super::KeyIndex;
+    use super::KeyInfo;
+    use super::KeyType;
     use super::Keyboard;
-    use super::MoveMap;
+    use super::Layout;
+    use super::ParseMode;
+    use super::Region;
+    use super::{ALL_DIRS, HARMONIC_TABLE, WICKI_HAYDEN};
+    use crate::tuning::{Edo, Interval, IntervalDirection, IntervalStep, MidiNote, Tuning, EDO12, EDO31};
+
+    /// A delta move.  Indicates A movement.  The hand written movement tables
+    /// are only kept to check the coordinates against.
+    #[derive(Debug, Copy, Clone)]
+    struct KeyMove {
+        pub group: i8,
+        pub key: u8,
+    }
+
+    pub(super) struct MoveMap(BTreeMap<Dir, Vec<Option<KeyMove>>>);
 
     impl MoveMap {
+        /// Construct the key movement mapping.
+        pub(super) fn make() -> MoveMap {
+            let mut result = BTreeMap::new();
+
+            // Movement to the right.
+            let mut right = Vec::with_capacity(56);
+            for i in 0..56 {
+                right.push(Some(KeyMove {group: 0, key: i + 1}));
+            }
+            // Two of the keys have nothing to the right.
+            right[1] = None;
+            right[6] = None;
+            // And several of the keys move to another group.
+            right[12] = Some(KeyMove { group: 1, key: 0 });
+            right[18] = Some(KeyMove { group: 1, key: 2 });
+            right[24] = Some(KeyMove { group: 1, key: 7 });
+            right[30] = Some(KeyMove { group: 1, key: 13 });
+            right[36] = Some(KeyMove { group: 1, key: 19 });
+            right[42] = Some(KeyMove { group: 1, key: 25 });
+            right[48] = Some(KeyMove { group: 1, key: 31 });
+            right[53] = Some(KeyMove { group: 1, key: 37 });
+            right[55] = Some(KeyMove { group: 1, key: 43 });
+
+            result.insert(Dir::Right, right);
+
+            // Movement to the left.
+            let mut left = Vec::with_capacity(56);
+            left.push(None);
+            for i in 1..56 {
+                left.push(Some(KeyMove {group: 0, key: i - 1}));
+            }
+            // Keys with nothing to the left.
+            left[49] = None;
+            left[54] = None;
+            // And the ones that move to a new group.
+            left[0] = Some(KeyMove { group: -1, key: 12 });
+            left[2] = Some(KeyMove { group: -1, key: 18 });
+            left[7] = Some(KeyMove { group: -1, key: 24 });
+            left[13] = Some(KeyMove { group: -1, key: 30 });
+            left[19] = Some(KeyMove { group: -1, key: 36 });
+            left[25] = Some(KeyMove { group: -1, key: 42 });
+            left[31] = Some(KeyMove { group: -1, key: 48 });
+            left[37] = Some(KeyMove { group: -1, key: 53 });
+            left[43] = Some(KeyMove { group: -1, key: 55 });
+
+            result.insert(Dir::Left, left);
+
+            // Movement down and right.
+            let mut dr: Vec<_> = [
+                /* 00 */ 2, 3,
+                /* 02 */ 8, 9, 10, 11, 12,
+                /* 07 */ 13, 14, 15, 16, 17, 18,
+                /* 13 */ 20, 21, 22, 23, 24, 7,
+                /* 19 */ 25, 26, 27, 28, 29, 30,
+                /* 25 */ 32, 33, 34, 35, 36, 19,
+                /* 31 */ 37, 38, 39, 40, 41, 42,
+                /* 37 */ 44, 45, 46, 47, 48, 31,
+                /* 43 */ 0, 49, 50, 51, 52, 53,
+                /* 49 */ 0, 0, 54, 55, 43,
+                /* 54 */ 0, 0,
+            ].iter().map(|&n| Some(KeyMove { group: 0, key: n })).collect();
+            // Keys with no movement DR.
+            dr[43] = None;
+            dr[49] = None;
+            dr[50] = None;
+            dr[54] = None;
+            dr[55] = None;
+            // Moves to next group.
+            if let Some(k) = dr[18].as_mut() { k.group = 1; }
+            if let Some(k) = dr[30].as_mut() { k.group = 1; }
+            if let Some(k) = dr[42].as_mut() { k.group = 1; }
+            if let Some(k) = dr[53].as_mut() { k.group = 1; }
+
+            result.insert(Dir::DownRight, dr);
+
+            // Movement up and left.
+            let mut ul: Vec<_> = [
+                /* 00 */ 0, 0,
+                /* 02 */ 0, 1, 0, 0, 0,
+                /* 07 */ 18, 2, 3, 4, 5, 6,
+                /* 13 */ 7, 8, 9, 10, 11, 12,
+                /* 19 */ 30, 13, 14, 15, 16, 17,
+                /* 25 */ 19, 20, 21, 22, 23, 24,
+                /* 31 */ 42, 25, 26, 27, 28, 29,
+                /* 37 */ 31, 32, 33, 34, 35, 36,
+                /* 43 */ 53, 37, 38, 39, 40, 41,
+                /* 49 */ 44, 45, 46, 47, 48,
+                /* 54 */ 51, 52,
+            ].iter().map(|&n| Some(KeyMove { group: 0, key: n })).collect();
+            // No movement UL
+            ul[0] = None;
+            ul[1] = None;
+            ul[4] = None;
+            ul[5] = None;
+            ul[6] = None;
+            // Moves to previous group.
+            if let Some(k) = ul[7].as_mut() { k.group = -1; }
+            if let Some(k) = ul[19].as_mut() { k.group = -1; }
+            if let Some(k) = ul[31].as_mut() { k.group = -1; }
+            if let Some(k) = ul[43].as_mut() { k.group = -1; }
+
+            result.insert(Dir::UpLeft, ul);
+
+            // Movement down and left.
+            let mut dl: Vec<_> = [
+                /* 00 */ 18, 2,
+                /* 02 */ 7, 8, 9, 10, 11,
+                /* 07 */ 30, 13, 14, 15, 16, 17,
+                /* 13 */ 19, 20, 21, 22, 23, 24,
+                /* 19 */ 42, 25, 26, 27, 28, 29,
+                /* 25 */ 31, 32, 33, 34, 35, 36,
+                /* 31 */ 53, 37, 38, 39, 40, 41,
+                /* 37 */ 43, 44, 45, 46, 47, 48,
+                /* 43 */ 0, 0, 49, 50, 51, 52,
+                /* 49 */ 0, 0, 0, 54, 55,
+                /* 54 */ 0, 0,
+            ].iter().map(|&n| Some(KeyMove { group: 0, key: n })).collect();
+            // Keys with no movement DR.
+            dl[43] = None;
+            dl[44] = None;
+            dl[49] = None;
+            dl[50] = None;
+            dl[51] = None;
+            dl[54] = None;
+            dl[55] = None;
+            // Moves to next group.
+            if let Some(k) = dl[0].as_mut() { k.group = -1; }
+            if let Some(k) = dl[7].as_mut() { k.group = -1; }
+            if let Some(k) = dl[19].as_mut() { k.group = -1; }
+            if let Some(k) = dl[31].as_mut() { k.group = -1; }
+
+            result.insert(Dir::DownLeft, dl);
+
+            // Movement up and right.
+            let mut ur: Vec<_> = [
+                /* 00 */ 0, 0,
+                /* 02 */ 1, 0, 0, 0, 0,
+                /* 07 */ 2, 3, 4, 5, 6, 0,
+                /* 13 */ 8, 9, 10, 11, 12, 0,
+                /* 19 */ 13, 14, 15, 16, 17, 18,
+                /* 25 */ 20, 21, 22, 23, 24, 7,
+                /* 31 */ 25, 26, 27, 28, 29, 30,
+                /* 37 */ 32, 33, 34, 35, 36, 19,
+                /* 43 */ 37, 38, 39, 40, 41, 42,
+                /* 49 */ 45, 46, 47, 48, 31,
+                /* 54 */ 52, 53,
+            ].iter().map(|&n| Some(KeyMove { group: 0, key: n })).collect();
+            // No movement UL
+            ur[0] = None;
+            ur[1] = None;
+            ur[3] = None;
+            ur[4] = None;
+            ur[5] = None;
+            ur[6] = None;
+            ur[12] = None;
+            // Moves to next group.
+            if let Some(k) = ur[18].as_mut() { k.group = 1; }
+            if let Some(k) = ur[30].as_mut() { k.group = 1; }
+            if let Some(k) = ur[42].as_mut() { k.group = 1; }
+            if let Some(k) = ur[53].as_mut() { k.group = 1; }
+
+            result.insert(Dir::UpRight, ur);
+
+
+            MoveMap(result)
+        }
+
+        // Return the new key from this movement.
+        pub(super) fn trymove(&self, key: KeyIndex, dir: Dir) -> Option<KeyIndex> {
+            // self.0.get(&dir).and_then(|moves| moves.get(key.key as usize))
+            self.0
+                .get(&dir)
+                .and_then(|moves| moves[key.key as usize])
+                .and_then(|mv| {
+                    // Check if we move off of the edge.
+                    if (mv.group < 0 && key.group == 0) ||
+                        (mv.group > 0 && key.group == 4)
+                    {
+                        None
+                    } else {
+                        Some(KeyIndex {
+                            group: (key.group as i8 + mv.group) as u8,
+                            key: mv.key,
+                        })
+                    }
+                })
+        }
+
         /// Verify that all movements in direction 'a' and then 'b' get back to the same place.
         fn check(&self, a: Dir, b: Dir) {
             for k1 in KeyIndex::iter_all() {
@@ -558,67 +816,149 @@ mod test {
         mv.check(Dir::UpRight, Dir::DownLeft);
         mv.check(Dir::DownLeft, Dir::UpRight);
     }
-}
 
-impl KeyIndex {
-    fn iter_all() -> KeyIndexIter {
-        KeyIndexIter {
-            group: 0,
-            key: 0,
-        }
+    /// Layouts can use intervals that aren't one of the named steps.
+    #[test]
+    fn lumatone_raw_intervals() {
+        // A neutral layout for 24-EDO, neutral seconds to the right, and
+        // neutral thirds up and to the right.
+        let layout = Layout::new(
+            Interval::steps(3, IntervalDirection::Up),
+            Interval::new(IntervalStep::MajorSecond, IntervalDirection::Up),
+            Interval::steps(7, IntervalDirection::Up),
+        );
+        let edo = Edo::new(24).unwrap();
+        let keyb = filled(&edo, &layout, KeyIndex { group: 2, key: 27 },
+                          Region::Columns { left: 4, right: 4 });
+        let note = |key| {
+            let info = keyb.get(KeyIndex { group: 2, key }).unwrap();
+            MidiNote { channel: info.channel, note: info.note }
+        };
+        assert_eq!(note(27), MidiNote { channel: 4, note: 60 });
+        assert_eq!(edo.cents(note(28)), Some(150.0));
+        assert_eq!(edo.cents(note(26)), Some(-150.0));
+
+        // Octaves and fifths.
+        let octave = Interval::new(IntervalStep::Octave, IntervalDirection::Down);
+        assert_eq!(edo.interval(note(27), octave), Some(MidiNote { channel: 3, note: 60 }));
+        let tone = Interval::vector(2, -1, IntervalDirection::Up);
+        assert_eq!(edo.interval(note(27), tone), Some(MidiNote { channel: 4, note: 64 }));
     }
-}
 
-struct KeyIndexIter {
-    group: u8,
-    key: u8,
-}
+    /// Fills report where they run into each other, or off the end of the
+    /// tuning.
+    #[test]
+    fn lumatone_fill_result() {
+        // The two halves of the 31-EDO split disagree along the boundary.
+        let mut keyb = Keyboard::default();
+        let fill = FillInfo::new(KeyIndex { group: 3, key: 47 }, Region::Columns { left: 8, right: 9 });
+        let right = keyb.fill_layout(&EDO31, &WICKI_HAYDEN, &fill).unwrap();
+        assert!(right.is_clean());
+        assert_eq!(right.filled.len(), 154);
+        let fill = FillInfo::new(KeyIndex { group: 1, key: 14 }, Region::Columns { left: 9, right: 9 });
+        let left = keyb.fill_layout(&EDO31, &WICKI_HAYDEN, &fill).unwrap();
+        assert_eq!(left.filled.len(), 124);
+        assert_eq!(left.conflicts.len(), 8);
+        let conflict = left.conflicts[0];
+        assert_ne!(conflict.existing, conflict.wanted);
+        let info = keyb.get(conflict.key).unwrap();
+        assert_eq!(MidiNote { channel: info.channel, note: info.note }, conflict.existing);
+
+        // Filling the same place again only collides.
+        let start = KeyIndex { group: 3, key: 47 };
+        let again = keyb.fill_layout(&EDO31, &WICKI_HAYDEN, &FillInfo::new(start, Region::All)).unwrap();
+        assert!(again.filled.is_empty());
+        assert_eq!(again.collisions, [start]);
+
+        // 12-EDO on a single channel runs out of midi notes.
+        let mut keyb = Keyboard::default();
+        let fill = FillInfo::new(KeyIndex::origin(), Region::All);
+        let result = keyb.fill_layout(&EDO12, &HARMONIC_TABLE, &fill).unwrap();
+        assert_eq!(result.filled.len(), 279);
+        assert_eq!(result.dropped.len(), 1);
+        let dropped = result.dropped[0];
+        assert_eq!(dropped.note, MidiNote { channel: 1, note: 125 });
+        assert_eq!(dropped.dir, Dir::Right);
+        assert!(keyb.get(dropped.key).is_none());
+    }
 
-impl Iterator for KeyIndexIter {
-    type Item = KeyIndex;
-    fn next(&mut self) -> Option<KeyIndex> {
-        if self.group >= 5 {
-            return None;
+    /// Fills pass over keys reserved for controllers, leaving the rest of the
+    /// layout where it would have been.
+    #[test]
+    fn lumatone_fill_reserved() {
+        let start = KeyIndex { group: 2, key: 27 };
+        let info = FillInfo::new(start, Region::All);
+        let mut plain = Keyboard::default();
+        plain.fill_layout(&EDO12, &WICKI_HAYDEN, &info).unwrap();
+
+        // Surround the start, and the start itself, with controllers.
+        let mut reserved: Vec<KeyIndex> = ALL_DIRS.iter().filter_map(|&dir| start.neighbor(dir)).collect();
+        reserved.push(start);
+        let mut keyb = Keyboard::default();
+        for &key in &reserved {
+            keyb.reserve(key, KeyInfo::controller(0, 64));
         }
-        let result = KeyIndex { group: self.group, key: self.key };
-        if self.key == 55 {
-            self.key = 0;
-            self.group += 1;
-        } else {
-            self.key += 1;
+        let result = keyb.fill_layout(&EDO12, &WICKI_HAYDEN, &info).unwrap();
+        assert!(result.is_clean());
+        assert_eq!(result.reserved.len(), 7);
+        assert_eq!(result.filled.len(), 280 - 7);
+        for key in KeyIndex::iter_all() {
+            let info = keyb.get(key).unwrap();
+            if reserved.contains(&key) {
+                assert_eq!((info.key_type, info.note), (KeyType::Controller, 64));
+            } else {
+                assert_eq!(info.note, plain.get(key).unwrap().note);
+            }
         }
-        Some(result)
+        assert_eq!(keyb.channels(), plain.channels());
     }
-}
 
-/// The offset and sizes of each for each row of the lumatone.
-static SIZES: [(u32, u32); 19] = [
-    (0, 2),
-    (0, 5),
-    (0, 8),
-    (0, 11),
-    (0, 14),
-    (0, 17),
-    (0, 20),
-    (0, 23),
-    (0, 26),
-    (1, 28),
-    (4, 26),
-    (7, 23),
-    (10, 20),
-    (13, 17),
-    (16, 14),
-    (19, 11),
-    (22, 8),
-    (25, 5),
-    (28, 2),
-    ];
+    /// Keyboards can be read and written without touching the disk.
+    #[test]
+    fn lumatone_in_memory() {
+        let keyb = filled(&EDO12, &WICKI_HAYDEN, KeyIndex { group: 2, key: 27 }, Region::All);
+
+        let text = keyb.ltn_string();
+        let mut bytes = Vec::new();
+        keyb.write_ltn_to(&mut bytes).unwrap();
+        assert_eq!(bytes, text.as_bytes());
+        let back = Keyboard::read_ltn(&bytes[..]).unwrap();
+        assert_eq!(back.ltn_string(), text);
+        assert_eq!(Keyboard::parse_ltn(&text).unwrap().ltn_string(), text);
+
+        let (_, warnings) = Keyboard::read_ltn_with(&b"[Board0]\nKey_0=1\nKey_0=2\n"[..], ParseMode::Lenient).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(Keyboard::read_ltn(&b"[Board0]\nKey_0=1\nKey_0=2\n"[..]).is_ok());
+        assert!(Keyboard::read_ltn(&b"[Board0]\nKey_0=\xff\n"[..]).is_err());
+
+        let svg = keyb.svg_string();
+        let mut bytes = Vec::new();
+        keyb.write_svg_to(&mut bytes).unwrap();
+        assert_eq!(bytes, svg.as_bytes());
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<path").count(), 280);
+    }
 
-/// Some colors for the sections.
-static SECTIONS: [RGB8; 5] = [
-    RGB8::new(204, 61, 61).lighten(),  // A pastel red
-    RGB8::new(175, 204, 61).lighten(), // A pastel lime green
-    RGB8::new(61, 204, 118).lighten(), // A pastel turquoise
-    RGB8::new(61, 118, 204).lighten(), // A pastel blue
-    RGB8::new(175, 61, 204).lighten(), // A pastel purple
-    ];
+    /// The starting key can hold notes other than middle C.
+    #[test]
+    fn lumatone_fill_anchor() {
+        let start = KeyIndex { group: 2, key: 27 };
+        let label = |anchor| {
+            let mut keyb = Keyboard::default();
+            keyb.fill_layout(&EDO31, &WICKI_HAYDEN, &FillInfo {
+                start,
+                anchor,
+                region: Region::Columns { left: 2, right: 2 },
+            })?;
+            Ok::<_, anyhow::Error>(keyb.get(start).unwrap().label.clone())
+        };
+        assert_eq!(label(Anchor::MiddleC).unwrap(), "C4");
+        assert_eq!(label(Anchor::Name("G2")).unwrap(), "G2");
+        assert_eq!(label(Anchor::Steps(-13)).unwrap(), "G3");
+        assert_eq!(label(Anchor::Steps(0)).unwrap(), "C4");
+        assert_eq!(label(Anchor::default()).unwrap(), "C4");
+        assert_eq!(label(Anchor::Note(MidiNote { channel: 4, note: 83 })).unwrap(), "A4");
+        assert!(label(Anchor::Name("H4")).is_err());
+        assert!(label(Anchor::Note(MidiNote { channel: 4, note: 100 })).is_err());
+    }
+}
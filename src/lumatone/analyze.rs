@@ -0,0 +1,249 @@
+//! Analysis of existing mappings.
+//!
+//! Work backwards from a keyboard, usually one loaded from an `.ltn` file, to
+//! the tuning, midi encoding, and isomorphic layout that it was built with.
+//! Each candidate encoding turns the keys into steps, and the best one is the
+//! encoding where the steps between neighbouring keys agree the most.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::tuning::{Blocks, ChannelOctaves, Edo, Encoding, MidiEncoding, MidiNote, Sequential, Tuning};
+
+use super::{Dir, KeyIndex, Keyboard, Layout, BOSANQUET, HARMONIC_TABLE, WICKI_HAYDEN};
+
+/// The layouts that an analysis will try to match.
+static KNOWN_LAYOUTS: [(&str, &Layout); 3] = [
+    ("Wicki-Hayden", &WICKI_HAYDEN),
+    ("Bosanquet", &BOSANQUET),
+    ("Harmonic table", &HARMONIC_TABLE),
+];
+
+/// The three axes of a layout, in the order right, up-left, up-right.
+const AXES: [Dir; 3] = [Dir::Right, Dir::UpLeft, Dir::UpRight];
+
+/// What was learned about a keyboard.
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    /// The best matching midi encoding.
+    pub encoding: Encoding,
+    /// The number of steps along the right, up-left, and up-right axes.
+    pub steps: [isize; 3],
+    /// The number of neighbouring pairs of keys that agree with these steps.
+    pub matched: usize,
+    /// The number of neighbouring pairs of keys that were compared.
+    pub total: usize,
+    /// The size of the EDO, and the number of steps in its fifth.  For
+    /// channel-octave encodings, the size always comes from the encoding,
+    /// otherwise it is only known when a layout matches.
+    pub edo: Option<(usize, Option<usize>)>,
+    /// The name of the known layout that matches the steps.
+    pub layout: Option<&'static str>,
+}
+
+impl Analysis {
+    /// Is every neighbouring pair of keys consistent with the steps.
+    pub fn is_isomorphic(&self) -> bool {
+        self.matched == self.total
+    }
+
+    /// Construct an Edo that can be used to regenerate this mapping, when the
+    /// size and fifth are known.
+    pub fn tuning(&self) -> Option<Edo> {
+        let (size, fifth) = self.edo?;
+        Edo::with_fifth(size, fifth?).ok()?.with_encoding(self.encoding).ok()
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.encoding {
+            Encoding::Sequential(enc) => write!(f, "single channel {}", enc.channel)?,
+            Encoding::ChannelOctaves(enc) => write!(f, "channel octaves from note {}", enc.bias)?,
+            Encoding::Blocks(enc) => write!(f, "128 note blocks from channel {}", enc.channel)?,
+        }
+        match self.edo {
+            Some((size, Some(fifth))) => write!(f, ", {}-EDO with a fifth of {}", size, fifth)?,
+            Some((size, None)) => write!(f, ", {}-EDO", size)?,
+            None => (),
+        }
+        write!(f, ", steps right {}, up-left {}, up-right {}",
+               self.steps[0], self.steps[1], self.steps[2])?;
+        write!(f, ", {}/{} neighbours agree", self.matched, self.total)?;
+        if let Some(layout) = self.layout {
+            write!(f, ", {}", layout)?;
+        }
+        Ok(())
+    }
+}
+
+impl Keyboard {
+    /// Work out the tuning and layout this keyboard was built with.  Returns
+    /// None if the keyboard doesn't have any neighbouring keys that can be
+    /// compared.
+    pub fn analyze(&self) -> Option<Analysis> {
+        let notes: Vec<(KeyIndex, MidiNote)> = KeyIndex::iter_all()
+            .filter_map(|index| {
+                let info = self.get(index).filter(|info| info.is_note())?;
+                Some((index, MidiNote { channel: info.channel, note: info.note }))
+            })
+            .collect();
+
+        let mut best: Option<(isize, Analysis)> = None;
+        for (encoding, size) in candidates(&notes) {
+            let Some(analysis) = axis_steps(&notes, encoding, size) else {
+                continue;
+            };
+            // Pairs that disagree count against the encoding, so that
+            // ignoring keys isn't better than explaining them.
+            let score = 2 * analysis.matched as isize - analysis.total as isize;
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, analysis));
+            }
+        }
+
+        let (_, mut analysis) = best?;
+        let size = match analysis.encoding {
+            Encoding::ChannelOctaves(_) => analysis.edo.map(|(size, _)| size),
+            _ => None,
+        };
+        if let Some((layout, size, fifth)) = match_layout(analysis.steps, size) {
+            analysis.layout = Some(layout);
+            analysis.edo = Some((size, Some(fifth)));
+        }
+        Some(analysis)
+    }
+}
+
+/// The encodings worth trying for these notes, along with the period size to
+/// use with them.
+fn candidates(notes: &[(KeyIndex, MidiNote)]) -> Vec<(Encoding, usize)> {
+    let mut counts = BTreeMap::new();
+    for (_, note) in notes {
+        *counts.entry(note.channel).or_insert(0) += 1;
+    }
+    let Some((&busiest, _)) = counts.iter().max_by_key(|(_, &count)| count) else {
+        return vec![];
+    };
+
+    let mut result = vec![(Encoding::Sequential(Sequential { channel: busiest, middle: 60 }), 1)];
+    if counts.len() > 1 {
+        let first = *counts.keys().next().unwrap();
+        result.push((Encoding::Blocks(Blocks { channel: first, middle: 60 }), 1));
+
+        let low = notes.iter().map(|(_, note)| note.note).min().unwrap();
+        let high = notes.iter().map(|(_, note)| note.note).max().unwrap();
+        for size in (high - low) as usize + 1..=128 - low as usize {
+            let enc = ChannelOctaves { bias: low, middle_channel: 4 };
+            result.push((Encoding::ChannelOctaves(enc), size));
+        }
+    }
+    result
+}
+
+/// Decode the keys with the given encoding, and find the most common number
+/// of steps along each axis.
+fn axis_steps(notes: &[(KeyIndex, MidiNote)], encoding: Encoding, size: usize) -> Option<Analysis> {
+    let steps: BTreeMap<KeyIndex, isize> = notes
+        .iter()
+        .filter_map(|&(index, note)| Some((index, encoding.decode(note, size)?)))
+        .collect();
+
+    let mut result = [0; 3];
+    let mut matched = 0;
+    let mut total = 0;
+    for (axis, dir) in AXES.iter().enumerate() {
+        let mut diffs: BTreeMap<isize, usize> = BTreeMap::new();
+        for (&index, &from) in &steps {
+            let Some(to) = index.neighbor(*dir).and_then(|next| steps.get(&next)) else {
+                continue;
+            };
+            *diffs.entry(to - from).or_insert(0) += 1;
+        }
+        let (&diff, &count) = diffs.iter().max_by_key(|(_, &count)| count)?;
+        result[axis] = diff;
+        matched += count;
+        total += diffs.values().sum::<usize>();
+    }
+
+    let edo = match encoding {
+        Encoding::ChannelOctaves(_) => Some((size, None)),
+        _ => None,
+    };
+    Some(Analysis { encoding, steps: result, matched, total, edo, layout: None })
+}
+
+/// Find a known layout, and an EDO, that give these steps along the axes.
+/// When the size is known, only that size is tried.  The fifth closest to
+/// just is tried for every size and layout before any of the other fifths, to
+/// avoid matching a layout in a strange tuning.
+fn match_layout(steps: [isize; 3], size: Option<usize>) -> Option<(&'static str, usize, usize)> {
+    let sizes = match size {
+        Some(size) => size..=size,
+        None => 5..=128,
+    };
+    let patent = sizes.clone().filter_map(|size| Some((size, Edo::new(size).ok()?.fifth())));
+    let others = sizes.flat_map(|size| {
+        let patent = Edo::new(size).ok().map(|edo| edo.fifth());
+        (1..size).filter(move |&fifth| Some(fifth) != patent).map(move |fifth| (size, fifth))
+    });
+    for (size, fifth) in patent.chain(others) {
+        let Ok(edo) = Edo::with_fifth(size, fifth) else {
+            continue;
+        };
+        for (name, layout) in &KNOWN_LAYOUTS {
+            let axes = [layout.right, layout.up_left, layout.up_right].map(|interval| {
+                let steps = edo.interval_steps(interval.size())?;
+                Some(if interval.is_up() { steps } else { -steps })
+            });
+            if axes == steps.map(Some) {
+                return Some((name, size, fifth));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lumatone::{filled, KeyIndex, Keyboard, Region, BOSANQUET, WICKI_HAYDEN};
+    use crate::tuning::{Encoding, EDO31};
+
+    #[test]
+    fn analyze_generated() {
+        let keyb = filled(&EDO31, &WICKI_HAYDEN, KeyIndex { group: 2, key: 27 },
+                          Region::Columns { left: 20, right: 20 });
+        let analysis = keyb.analyze().unwrap();
+        assert!(analysis.is_isomorphic());
+        assert_eq!(analysis.steps, [5, 13, 18]);
+        assert_eq!(analysis.edo, Some((31, Some(18))));
+        assert_eq!(analysis.layout, Some("Wicki-Hayden"));
+        let Encoding::ChannelOctaves(enc) = analysis.encoding else {
+            panic!("Expecting channel octaves");
+        };
+        assert_eq!(enc.bias, 60);
+    }
+
+    #[test]
+    fn analyze_factory() {
+        let keyb = Keyboard::load("data/lumatone_Wicki-Hayden_v3.ltn").unwrap();
+        let analysis = keyb.analyze().unwrap();
+        assert_eq!(analysis.layout, Some("Wicki-Hayden"));
+        assert_eq!(analysis.edo, Some((12, Some(7))));
+
+        let keyb = Keyboard::load("data/factory-2-harmonic-table.ltn").unwrap();
+        let analysis = keyb.analyze().unwrap();
+        assert_eq!(analysis.layout, Some("Harmonic table"));
+        assert!(analysis.is_isomorphic());
+
+        // The factory 31-EDO layout runs the notes across two channels.
+        let keyb = Keyboard::load("data/factory-8-31.ltn").unwrap();
+        let analysis = keyb.analyze().unwrap();
+        assert_eq!(analysis.layout, Some("Bosanquet"));
+        assert!(matches!(analysis.encoding, Encoding::Blocks(_)));
+        assert_eq!(analysis.tuning().unwrap().octave(), 31);
+
+        let keyb = filled(&EDO31, &BOSANQUET, KeyIndex { group: 2, key: 27 },
+                          Region::Columns { left: 20, right: 20 });
+        assert_eq!(keyb.analyze().unwrap().layout, Some("Bosanquet"));
+    }
+}
@@ -0,0 +1,311 @@
+//! Lumatone key coordinates.
+//!
+//! The keys of each board are numbered across the rows, from the top, and
+//! each board is placed two rows down, and five keys to the right, of the one
+//! before.  This gives a closed form for the position of every key, so moving
+//! between keys, or across the whole keyboard, is just arithmetic.
+//!
+//! Positions are given in two forms.  `Hex` is axial hex coordinates: `r` is
+//! the row, counting down from 0 at the top of the keyboard (key 0 of the
+//! first board), and `q` goes up by one moving to the right, and stays the
+//! same moving up-left, so moving up-right goes up by one in `q` and down by
+//! one in `r`.  `Offset` is the physical row and column, as the keys are drawn,
+//! with odd rows shifted half a key to the right.
+
+use super::{Dir, KeyIndex};
+
+/// The number of boards (groups) on the keyboard.
+const BOARDS: u8 = 5;
+
+/// The first key of each row of a board, and the end of the last row.
+const ROW_FIRST: [u8; 12] = [0, 2, 7, 13, 19, 25, 31, 37, 43, 49, 54, 56];
+
+/// The `q` of the first key of each row of a board.
+const ROW_Q: [isize; 11] = [0, 0, -1, -1, -2, -2, -3, -3, -4, -3, -1];
+
+/// How far each board is from the one before.
+const BOARD_STEP: Hex = Hex::new(5, 2);
+
+/// All of the directions, in the order of the `Dir` enum.
+pub const ALL_DIRS: [Dir; 6] = [Dir::UpLeft, Dir::UpRight, Dir::Right, Dir::DownRight, Dir::DownLeft, Dir::Left];
+
+/// A position on the hex grid, in axial coordinates.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Hex {
+    pub q: isize,
+    pub r: isize,
+}
+
+/// A physical position, by row and column.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Offset {
+    pub row: isize,
+    pub col: isize,
+}
+
+impl Hex {
+    pub const fn new(q: isize, r: isize) -> Hex {
+        Hex { q, r }
+    }
+
+    /// The position one key away in the given direction.
+    pub const fn step(self, dir: Dir) -> Hex {
+        let delta = dir.delta();
+        Hex::new(self.q + delta.q, self.r + delta.r)
+    }
+
+    /// The number of keys crossed moving from this position to another.
+    pub const fn distance(self, other: Hex) -> usize {
+        let dq = other.q - self.q;
+        let dr = other.r - self.r;
+        (dq.unsigned_abs() + dr.unsigned_abs() + (dq + dr).unsigned_abs()) / 2
+    }
+
+    /// The positions along a straight line from this position to another,
+    /// including both ends.
+    pub fn line(self, other: Hex) -> Vec<Hex> {
+        let steps = self.distance(other);
+        if steps == 0 {
+            return vec![self];
+        }
+        // Nudge the line slightly, so that points exactly between two keys
+        // always round the same way.
+        let (q0, r0) = (self.q as f64 + 1e-6, self.r as f64 + 1e-6);
+        let (q1, r1) = (other.q as f64 + 1e-6, other.r as f64 + 1e-6);
+        (0..=steps)
+            .map(|i| {
+                let t = i as f64 / steps as f64;
+                round(q0 + (q1 - q0) * t, r0 + (r1 - r0) * t)
+            })
+            .collect()
+    }
+
+    /// The physical row and column of this position.
+    pub const fn offset(self) -> Offset {
+        Offset { row: self.r, col: self.q + self.r.div_euclid(2) }
+    }
+
+    /// The key at this position, if there is one.
+    pub const fn key(self) -> Option<KeyIndex> {
+        let mut group = 0;
+        while group < BOARDS {
+            let row = self.r - BOARD_STEP.r * group as isize;
+            if row >= 0 && row < ROW_Q.len() as isize {
+                let row = row as usize;
+                let pos = self.q - BOARD_STEP.q * group as isize - ROW_Q[row];
+                let len = (ROW_FIRST[row + 1] - ROW_FIRST[row]) as isize;
+                if pos >= 0 && pos < len {
+                    return Some(KeyIndex { group, key: ROW_FIRST[row] + pos as u8 });
+                }
+            }
+            group += 1;
+        }
+        None
+    }
+}
+
+impl Offset {
+    pub const fn new(row: isize, col: isize) -> Offset {
+        Offset { row, col }
+    }
+
+    /// The axial coordinates of this position.
+    pub const fn hex(self) -> Hex {
+        Hex { q: self.col - self.row.div_euclid(2), r: self.row }
+    }
+}
+
+impl Dir {
+    /// The change in position moving one key in this direction.
+    pub const fn delta(self) -> Hex {
+        match self {
+            Dir::UpLeft => Hex::new(0, -1),
+            Dir::UpRight => Hex::new(1, -1),
+            Dir::Right => Hex::new(1, 0),
+            Dir::DownRight => Hex::new(0, 1),
+            Dir::DownLeft => Hex::new(-1, 1),
+            Dir::Left => Hex::new(-1, 0),
+        }
+    }
+
+    /// The opposite direction.
+    pub const fn flip(self) -> Dir {
+        match self {
+            Dir::UpLeft => Dir::DownRight,
+            Dir::UpRight => Dir::DownLeft,
+            Dir::Right => Dir::Left,
+            Dir::DownRight => Dir::UpLeft,
+            Dir::DownLeft => Dir::UpRight,
+            Dir::Left => Dir::Right,
+        }
+    }
+}
+
+impl KeyIndex {
+    /// Is this a key on the keyboard.
+    pub const fn is_valid(self) -> bool {
+        self.group < BOARDS && self.key < ROW_FIRST[ROW_Q.len()]
+    }
+
+    /// The position of this key, if it is a key on the keyboard.
+    pub const fn hex(self) -> Option<Hex> {
+        if !self.is_valid() {
+            return None;
+        }
+        Some(self.position())
+    }
+
+    /// The position of a valid key.
+    const fn position(self) -> Hex {
+        let mut row = 0;
+        while self.key >= ROW_FIRST[row + 1] {
+            row += 1;
+        }
+        Hex {
+            q: ROW_Q[row] + (self.key - ROW_FIRST[row]) as isize + BOARD_STEP.q * self.group as isize,
+            r: row as isize + BOARD_STEP.r * self.group as isize,
+        }
+    }
+
+    /// The physical row and column of this key, if it is a key on the
+    /// keyboard.
+    pub const fn offset(self) -> Option<Offset> {
+        match self.hex() {
+            Some(hex) => Some(hex.offset()),
+            None => None,
+        }
+    }
+
+    /// The key at this position, if there is one.
+    pub const fn from_hex(hex: Hex) -> Option<KeyIndex> {
+        hex.key()
+    }
+
+    /// The key at this row and column, if there is one.
+    pub const fn from_offset(offset: Offset) -> Option<KeyIndex> {
+        offset.hex().key()
+    }
+
+    /// The neighbouring key in the given direction, if there is one.
+    pub const fn neighbor(self, dir: Dir) -> Option<KeyIndex> {
+        if !self.is_valid() {
+            return None;
+        }
+        NEIGHBORS[self.group as usize][self.key as usize][dir as usize]
+    }
+
+    /// The number of keys crossed moving from this key to another.  None if
+    /// either isn't a key on the keyboard.
+    pub fn distance(self, other: KeyIndex) -> Option<usize> {
+        Some(self.hex()?.distance(other.hex()?))
+    }
+
+    /// The keys along a straight line from this key to another, including
+    /// both ends.  None if the line leaves the keyboard.
+    pub fn line(self, other: KeyIndex) -> Option<Vec<KeyIndex>> {
+        self.hex()?.line(other.hex()?).into_iter().map(Hex::key).collect()
+    }
+}
+
+/// The neighbours of every key, in each direction.
+static NEIGHBORS: [[[Option<KeyIndex>; 6]; 56]; BOARDS as usize] = neighbors();
+
+const fn neighbors() -> [[[Option<KeyIndex>; 6]; 56]; BOARDS as usize] {
+    let mut table = [[[None; 6]; 56]; BOARDS as usize];
+    let mut group = 0;
+    while group < BOARDS {
+        let mut key = 0;
+        while key < 56 {
+            let hex = KeyIndex { group, key }.position();
+            let mut dir = 0;
+            while dir < ALL_DIRS.len() {
+                table[group as usize][key as usize][dir] = hex.step(ALL_DIRS[dir]).key();
+                dir += 1;
+            }
+            key += 1;
+        }
+        group += 1;
+    }
+    table
+}
+
+/// Round fractional axial coordinates to the nearest position.
+fn round(q: f64, r: f64) -> Hex {
+    let s = -q - r;
+    let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+        rq = -rr - rs;
+    } else if dr > ds {
+        rr = -rq - rs;
+    }
+    Hex::new(rq as isize, rr as isize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Hex, Offset, ALL_DIRS};
+    use super::Dir;
+    use crate::lumatone::{test::MoveMap, KeyIndex};
+
+    #[test]
+    fn coord_positions() {
+        assert_eq!(KeyIndex::origin().hex(), Some(Hex::new(0, 0)));
+        assert_eq!(KeyIndex { group: 0, key: 1 }.hex(), Some(Hex::new(1, 0)));
+        // Key 2 is down and to the right of key 0.
+        assert_eq!(KeyIndex { group: 0, key: 2 }.hex(), Some(Hex::new(0, 1)));
+        assert_eq!(KeyIndex { group: 1, key: 0 }.hex(), Some(Hex::new(5, 2)));
+        assert_eq!(KeyIndex { group: 4, key: 55 }.offset(), Some(Offset::new(18, 29)));
+
+        for key in KeyIndex::iter_all() {
+            let hex = key.hex().unwrap();
+            let offset = key.offset().unwrap();
+            assert_eq!(KeyIndex::from_hex(hex), Some(key));
+            assert_eq!(KeyIndex::from_offset(offset), Some(key));
+            assert_eq!(offset.hex(), hex);
+        }
+        assert_eq!(KeyIndex::from_hex(Hex::new(-1, 0)), None);
+
+        // Keys off the keyboard have no position.
+        for bad in [KeyIndex { group: 0, key: 56 }, KeyIndex { group: 5, key: 0 }] {
+            assert!(!bad.is_valid());
+            assert_eq!(bad.hex(), None);
+            assert_eq!(bad.offset(), None);
+            assert_eq!(bad.neighbor(Dir::Right), None);
+            assert_eq!(bad.distance(KeyIndex::origin()), None);
+            assert_eq!(bad.line(KeyIndex::origin()), None);
+        }
+    }
+
+    /// The closed form agrees with the hand written movement tables.
+    #[test]
+    fn coord_neighbors() {
+        let mv = MoveMap::make();
+        for key in KeyIndex::iter_all() {
+            for dir in ALL_DIRS {
+                assert_eq!(key.neighbor(dir), mv.trymove(key, dir), "{:?} {:?}", key, dir);
+                if let Some(next) = key.neighbor(dir) {
+                    assert_eq!(next.neighbor(dir.flip()), Some(key));
+                    assert_eq!(key.distance(next), Some(1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn coord_lines() {
+        let a = KeyIndex { group: 0, key: 7 };
+        let b = KeyIndex { group: 4, key: 48 };
+        let line = a.line(b).unwrap();
+        assert_eq!(Some(line.len()), a.distance(b).map(|d| d + 1));
+        assert_eq!(line.first(), Some(&a));
+        assert_eq!(line.last(), Some(&b));
+        for pair in line.windows(2) {
+            assert_eq!(pair[0].distance(pair[1]), Some(1));
+        }
+
+        // Straight across the top row leaves the keyboard.
+        assert_eq!(KeyIndex::origin().line(KeyIndex { group: 4, key: 1 }), None);
+        assert_eq!(Hex::new(0, 0).line(Hex::new(3, 0)).len(), 4);
+    }
+}
@@ -0,0 +1,254 @@
+//! Pitch class coverage.
+//!
+//! How much of a tuning a filled keyboard actually reaches.  Splits often
+//! don't have room for every pitch class on both sides, and some layouts only
+//! reach a pitch class in some of the octaves.  The coverage is given for
+//! each pitch class, and for each fill region, the pitch classes it is
+//! missing.
+
+use std::{collections::BTreeSet, fmt::{self, Write}, path::Path};
+
+use anyhow::Result;
+
+use crate::tuning::{Interval, IntervalDirection, MidiNote, Tuning};
+
+use super::{KeyIndex, Keyboard};
+
+/// The coverage of a single pitch class.
+#[derive(Debug, Clone)]
+pub struct PitchCoverage {
+    /// The number of steps of this pitch class above C.
+    pub pitch: usize,
+    /// The name of the pitch class, without the octave.
+    pub name: String,
+    /// How many keys play this pitch class.
+    pub keys: usize,
+    /// The octaves this pitch class can be played in, with middle C starting
+    /// octave 4.
+    pub octaves: Vec<isize>,
+}
+
+/// The coverage of a whole keyboard.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// The number of steps in each period of the tuning.
+    pub period: usize,
+    /// The number of keys that play a note of the tuning.
+    pub keys: usize,
+    /// The range from the lowest to the highest key, in octaves.
+    pub range: f64,
+    /// Every pitch class of the tuning, in order.
+    pub pitches: Vec<PitchCoverage>,
+    /// For each fill region, the pitch classes that none of its keys play.
+    pub missing: Vec<Vec<usize>>,
+}
+
+impl Keyboard {
+    /// Work out the coverage of this keyboard.  The regions are the keys
+    /// filled by each fill, from the results of `fill_layout`.  Returns None
+    /// if the tuning doesn't have a period to divide the notes into pitch
+    /// classes.
+    pub fn coverage(&self, tuning: &dyn Tuning, regions: &[Vec<KeyIndex>]) -> Option<Coverage> {
+        let period = tuning.period_steps()?;
+        let note = |index: KeyIndex| {
+            let info = self.get(index).filter(|info| info.is_note())?;
+            Some(MidiNote { channel: info.channel, note: info.note })
+        };
+        let steps = |index: KeyIndex| tuning.steps(note(index)?);
+
+        let mut pitches: Vec<PitchCoverage> = (0..period)
+            .map(|pitch| PitchCoverage {
+                pitch,
+                name: pitch_name(tuning, pitch),
+                keys: 0,
+                octaves: vec![],
+            })
+            .collect();
+        let mut octaves = vec![BTreeSet::new(); period];
+        let mut low = f64::INFINITY;
+        let mut high = f64::NEG_INFINITY;
+        let mut keys = 0;
+        for index in KeyIndex::iter_all() {
+            let Some(steps) = steps(index) else {
+                continue;
+            };
+            let pitch = steps.rem_euclid(period as isize) as usize;
+            pitches[pitch].keys += 1;
+            octaves[pitch].insert(4 + steps.div_euclid(period as isize));
+            if let Some(cents) = note(index).and_then(|note| tuning.cents(note)) {
+                low = low.min(cents);
+                high = high.max(cents);
+            }
+            keys += 1;
+        }
+        for (pitch, octaves) in pitches.iter_mut().zip(octaves) {
+            pitch.octaves = octaves.into_iter().collect();
+        }
+        let range = if high >= low { (high - low) / 1200.0 } else { 0.0 };
+
+        let missing = regions
+            .iter()
+            .map(|region| {
+                let present: BTreeSet<usize> = region
+                    .iter()
+                    .filter_map(|&index| steps(index))
+                    .map(|steps| steps.rem_euclid(period as isize) as usize)
+                    .collect();
+                (0..period).filter(|pitch| !present.contains(pitch)).collect()
+            })
+            .collect();
+
+        Some(Coverage { period, keys, range, pitches, missing })
+    }
+}
+
+impl Coverage {
+    /// Is every pitch class present in every region.
+    pub fn is_complete(&self) -> bool {
+        self.missing.iter().all(|missing| missing.is_empty())
+            && self.pitches.iter().all(|pitch| pitch.keys > 0)
+    }
+
+    /// Generate a JSON description of the coverage.
+    pub fn json(&self) -> String {
+        let mut out = String::new();
+        self.write_json_text(&mut out).expect("Writing to a String can't fail");
+        out
+    }
+
+    fn write_json_text(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"period\": {},", self.period)?;
+        writeln!(out, "  \"keys\": {},", self.keys)?;
+        writeln!(out, "  \"range\": {:.3},", self.range)?;
+        writeln!(out, "  \"pitches\": [")?;
+        for (i, pitch) in self.pitches.iter().enumerate() {
+            let comma = if i + 1 < self.pitches.len() { "," } else { "" };
+            writeln!(out, "    {{\"pitch\": {}, \"name\": {}, \"keys\": {}, \"octaves\": [{}]}}{}",
+                     pitch.pitch, json_string(&pitch.name), pitch.keys, join(&pitch.octaves, ", "),
+                     comma)?;
+        }
+        writeln!(out, "  ],")?;
+        writeln!(out, "  \"missing\": [")?;
+        for (i, missing) in self.missing.iter().enumerate() {
+            let comma = if i + 1 < self.missing.len() { "," } else { "" };
+            writeln!(out, "    [{}]{}", join(missing, ", "), comma)?;
+        }
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")
+    }
+
+    /// Write out the coverage as text.
+    pub fn write_text<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Write out the coverage as JSON.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.json())?;
+        Ok(())
+    }
+
+    /// The names of the given pitch classes.
+    fn names(&self, pitches: &[usize]) -> String {
+        let names: Vec<&str> = pitches.iter().map(|&pitch| self.pitches[pitch].name.as_str()).collect();
+        names.join(" ")
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} pitch classes, {} keys, range {:.2} octaves", self.period, self.keys, self.range)?;
+        writeln!(f)?;
+        writeln!(f, "pitch  name    keys  octaves")?;
+        for pitch in &self.pitches {
+            writeln!(f, "{:5}  {:6}  {:4}  {}", pitch.pitch, pitch.name, pitch.keys, join(&pitch.octaves, " "))?;
+        }
+        writeln!(f)?;
+
+        let unused: Vec<usize> = self.pitches.iter().filter(|pitch| pitch.keys == 0).map(|pitch| pitch.pitch).collect();
+        if !unused.is_empty() {
+            writeln!(f, "Not on the keyboard: {}", self.names(&unused))?;
+        }
+        for (i, missing) in self.missing.iter().enumerate() {
+            if missing.is_empty() {
+                writeln!(f, "Region {}: every pitch class", i + 1)?;
+            } else {
+                writeln!(f, "Region {}: missing {}", i + 1, self.names(missing))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The name of a pitch class, which is the name of the note that many steps
+/// above middle C, without the octave number.
+fn pitch_name(tuning: &dyn Tuning, pitch: usize) -> String {
+    let up = Interval::steps(pitch as isize, IntervalDirection::Up);
+    let Some(note) = tuning.interval(tuning.middle_c(), up) else {
+        return String::new();
+    };
+    let name = tuning.name(note, true);
+    name.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '-').to_string()
+}
+
+fn join<T: ToString>(items: &[T], sep: &str) -> String {
+    let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+    items.join(sep)
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lumatone::{FillInfo, KeyIndex, Keyboard, Region, HARMONIC_TABLE, WICKI_HAYDEN};
+    use crate::tuning::{EDO12, EDO31};
+
+    #[test]
+    fn coverage_harmonic() {
+        let mut keyb = Keyboard::default();
+        let fill = FillInfo::new(KeyIndex { group: 2, key: 39 }, Region::Columns { left: 16, right: 16 });
+        let result = keyb.fill_layout(&EDO31, &HARMONIC_TABLE, &fill).unwrap();
+        let coverage = keyb.coverage(&EDO31, &[result.filled]).unwrap();
+        assert_eq!(coverage.period, 31);
+        assert_eq!(coverage.keys, 276);
+        assert_eq!(coverage.pitches[0].name, "C");
+        assert_eq!(coverage.pitches[18].name, "G");
+
+        // Each pitch class is only reached every other octave.
+        for pitch in &coverage.pitches {
+            for pair in pitch.octaves.windows(2) {
+                assert_eq!(pair[1] - pair[0], 2);
+            }
+        }
+    }
+
+    #[test]
+    fn coverage_regions() {
+        let mut keyb = Keyboard::default();
+        let fills = [
+            FillInfo::new(KeyIndex { group: 3, key: 47 }, Region::Columns { left: 8, right: 9 }),
+            FillInfo::new(KeyIndex { group: 1, key: 14 }, Region::Columns { left: 9, right: 9 }),
+        ];
+        let regions: Vec<_> = fills.iter().map(|fill| keyb.fill_layout(&EDO12, &WICKI_HAYDEN, fill).unwrap().filled).collect();
+        let coverage = keyb.coverage(&EDO12, &regions).unwrap();
+        assert!(coverage.is_complete());
+        assert_eq!(coverage.keys, 280);
+        assert!(coverage.range > 4.0);
+        assert!(coverage.to_string().contains("Region 2: every pitch class"));
+        assert!(coverage.json().contains("{\"pitch\": 1, \"name\": \"C♯\", \"keys\": "));
+    }
+}
@@ -2,31 +2,81 @@
 //!
 //! Implements a graph fill for the Lumatone.
 
-use std::collections::VecDeque;
+use std::{collections::{BTreeMap, BTreeSet, VecDeque}, fmt};
 
 use crate::tuning::{MidiNote, Tuning};
 
-use super::{Dir, FillInfo, KeyIndex, KeyInfo, Keyboard, Layout, MoveMap};
+use super::{Dir, FillInfo, KeyIndex, KeyInfo, Keyboard, Layout};
 
 pub struct Filler<'k, 't, 'l, 'f> {
     keyboard: &'k mut Keyboard,
     tuning: &'t dyn Tuning,
     layout: &'l Layout,
-    info: &'f FillInfo,
+    info: &'f FillInfo<'f>,
 
     /// Cells that need to be filled in.
     work: VecDeque<Work>,
+}
+
+/// What happened during a fill.
+#[derive(Debug, Clone, Default)]
+pub struct FillResult {
+    /// The keys that were filled in, in the order they were filled.
+    pub filled: Vec<KeyIndex>,
+    /// Keys in the region that were already filled in, by an earlier fill,
+    /// with the same note this fill would have given them.
+    pub collisions: Vec<KeyIndex>,
+    /// Keys in the region that already held a different note than this fill
+    /// would have given them.
+    pub conflicts: Vec<Conflict>,
+    /// Keys in the region that were left empty, because the tuning couldn't
+    /// move to them from a neighbouring key.
+    pub dropped: Vec<Dropped>,
+    /// Keys in the region that were reserved for something other than a
+    /// note, which the fill passed over.
+    pub reserved: Vec<KeyIndex>,
+}
+
+/// A key where the fill disagreed with what was already there.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Conflict {
+    pub key: KeyIndex,
+    /// The note already on the key, which is kept.
+    pub existing: MidiNote,
+    /// The note this fill wanted to put there.
+    pub wanted: MidiNote,
+}
+
+/// A key that couldn't be given a note.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Dropped {
+    pub key: KeyIndex,
+    /// The neighbouring key the fill tried to move from.
+    pub from: KeyIndex,
+    /// The note on that neighbouring key.
+    pub note: MidiNote,
+    /// The direction of the move.
+    pub dir: Dir,
+}
 
-    mv: MoveMap,
+impl FillResult {
+    /// Did everything in the region get filled without any disagreement.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty() && self.dropped.is_empty()
+    }
+}
+
+impl fmt::Display for FillResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} keys filled, {} shared with earlier fills, {} conflicting, {} dropped",
+               self.filled.len(), self.collisions.len(), self.conflicts.len(), self.dropped.len())
+    }
 }
 
 /// A single unit of work for the filler.  Indicates a cell that should be
 /// filled.
 #[derive(Debug)]
 struct Work {
-    /// The horizontal position of this cell.  Used to limit the horizontal
-    /// bounds of the work.
-    x: isize,
     /// The position we want to fill.
     pos: KeyIndex,
     /// The note value for this cell.
@@ -54,33 +104,39 @@ enum Cardinal {
 }
 
 impl<'k, 't, 'l, 'f> Filler<'k, 't, 'l, 'f> {
-    /// Construct a new filler with the given information.
+    /// Construct a new filler with the given information, placing the anchor
+    /// note on the starting key.
     pub fn new(
         keyboard: &'k mut Keyboard,
         tuning: &'t dyn Tuning,
         layout: &'l Layout,
-        info: &'f FillInfo,
+        info: &'f FillInfo<'f>,
+        anchor: MidiNote,
     ) -> Filler<'k, 't, 'l, 'f>
     {
         // Create the initial work.
         let first_cell = Work {
-            x: 0,
             pos: info.start,
-            note: tuning.middle_c(),
+            note: anchor,
             phase: Phase::Left,
             increasing: true,
         };
         let mut work = VecDeque::new();
         work.push_back(first_cell);
 
-        let mv = MoveMap::make();
-
-        Filler { keyboard, tuning, layout, info, work, mv }
+        Filler { keyboard, tuning, layout, info, work }
     }
 
     /// Run the filler, filling in the grid according to all of the work.
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> FillResult {
         let mut first = true;
+        let mut filled = Vec::new();
+        let mut own = BTreeSet::new();
+        let mut collisions = BTreeSet::new();
+        let mut conflicts: BTreeMap<KeyIndex, Conflict> = BTreeMap::new();
+        let mut dropped: BTreeMap<KeyIndex, Dropped> = BTreeMap::new();
+        let mut reserved = Vec::new();
+        let mut passed = BTreeSet::new();
 
         while let Some(work) = self.work.pop_front() {
             let cell =
@@ -90,38 +146,58 @@ impl<'k, 't, 'l, 'f> Filler<'k, 't, 'l, 'f> {
                     // Out of bounds.  This really shouldn't happen.
                     unreachable!()
                 };
-            if let Some(cell) = cell {
+            if cell.as_ref().is_some_and(|cell| !cell.is_note()) {
+                // Reserved keys don't get a note, but the fill carries on
+                // past them, as if they had been filled.
+                if !self.info.region.contains(work.pos, self.info.start) || !passed.insert(work.pos) {
+                    continue;
+                }
+                reserved.push(work.pos);
+            } else if let Some(cell) = cell {
                 // If the cell is already filled in, don't fill any more in from
                 // this location.
                 // However, lighten this square to help visualize where the boundary is.
                 // But, only do this when we are filling with a different value than what is there.
-                if cell.channel != work.note.channel || cell.note != work.note.note {
+                let existing = MidiNote { channel: cell.channel, note: cell.note };
+                let differs = existing != work.note;
+                if differs {
                     cell.color = cell.color.lighten();
                 }
+                if self.info.region.contains(work.pos, self.info.start) {
+                    if differs {
+                        conflicts.entry(work.pos).or_insert(Conflict {
+                            key: work.pos,
+                            existing,
+                            wanted: work.note,
+                        });
+                    } else if !own.contains(&work.pos) {
+                        collisions.insert(work.pos);
+                    }
+                }
                 continue;
-            }
+            } else {
+                // Ensure that the cell is within the requested region.
+                if !self.info.region.contains(work.pos, self.info.start) {
+                    continue;
+                }
 
-            // Ensure that the x position is within the requested bounds.
-            if work.x > self.info.right as isize || work.x < -(self.info.left as isize) {
-                // println!("  x out of bounds: {:?}", work);
-                continue;
+                // We have all of the information for this cell.
+                *cell = Some(KeyInfo {
+                    channel: work.note.channel,
+                    note: work.note.note,
+                    color: self.tuning.color(work.note, work.increasing),
+                    label: self.tuning.name(work.note, work.increasing),
+                    // label: format!("{}->{}", work.from, count),
+                    ..KeyInfo::default()
+                });
+                filled.push(work.pos);
+                own.insert(work.pos);
             }
 
-            // We have all of the information for this cell.
-            *cell = Some(KeyInfo {
-                channel: work.note.channel,
-                note: work.note.note,
-                color: self.tuning.color(work.note, work.increasing),
-                label: self.tuning.name(work.note, work.increasing),
-                // label: format!("{}->{}", work.from, count),
-            });
-
             // Generate additional work for everything adjacent.
             for card in Cardinal::iter() {
-                let x = work.x + card.x_offset();
-
                 // New position, if possible.
-                let pos = if let Some(pos) = work.phase.pos_move(self, work.pos, card) {
+                let pos = if let Some(pos) = work.phase.pos_move(work.pos, card) {
                     pos
                 } else {
                     continue;
@@ -131,6 +207,14 @@ impl<'k, 't, 'l, 'f> Filler<'k, 't, 'l, 'f> {
                 let note = if let Some(note) = work.phase.note_move(self, work.note, card) {
                     note
                 } else {
+                    if self.info.region.contains(pos, self.info.start) {
+                        dropped.entry(pos).or_insert(Dropped {
+                            key: pos,
+                            from: work.pos,
+                            note: work.note,
+                            dir: work.phase.dir(card),
+                        });
+                    }
                     continue;
                 };
 
@@ -138,11 +222,23 @@ impl<'k, 't, 'l, 'f> Filler<'k, 't, 'l, 'f> {
                 let increasing = if first { card.is_increasing() } else { work.increasing };
                 let phase = card.new_phase(work.phase);
 
-                self.work.push_back(Work { x, pos, note, phase, increasing });
+                self.work.push_back(Work { pos, note, phase, increasing });
             }
 
             first = false;
         }
+
+        // Only the keys that didn't get filled some other way were dropped.
+        let keyboard = &*self.keyboard;
+        dropped.retain(|&key, _| keyboard.get(key).is_none());
+
+        FillResult {
+            filled,
+            collisions: collisions.into_iter().collect(),
+            conflicts: conflicts.into_values().collect(),
+            dropped: dropped.into_values().collect(),
+            reserved,
+        }
     }
 }
 
@@ -159,15 +255,6 @@ impl Cardinal {
         ALL_CARDINALS.iter().cloned()
     }
 
-    /// For a given cardinal direction, return the x adjustment.
-    pub fn x_offset(self) -> isize {
-        match self {
-            Cardinal::Left => -1,
-            Cardinal::Right => 1,
-            _ => 0,
-        }
-    }
-
     /// Find the new phase for the given direction.
     pub fn new_phase(self, phase: Phase) -> Phase {
         match self {
@@ -178,10 +265,7 @@ impl Cardinal {
 
     /// Is this direction "increasing", meaning it should have higher note values.
     pub fn is_increasing(self) -> bool {
-        match self {
-            Cardinal::Up | Cardinal::Right => true,
-            _ => false,
-        }
+        matches!(self, Cardinal::Up | Cardinal::Right)
     }
 }
 
@@ -209,9 +293,8 @@ impl Phase {
     }
 
     /// Move this cell, according to the given direction.
-    pub fn pos_move(self, filler: &Filler, pos: KeyIndex, card: Cardinal) -> Option<KeyIndex> {
-        let dir = self.dir(card);
-        filler.mv.trymove(pos, dir)
+    pub fn pos_move(self, pos: KeyIndex, card: Cardinal) -> Option<KeyIndex> {
+        pos.neighbor(self.dir(card))
     }
 
     /// Move this note, according to the given direction.
@@ -1,133 +1,847 @@
 //! Lumatone LTN file reading.
+//!
+//! An LTN file is kept as the lines it was read from, each parsed into an
+//! entry.  Lines that aren't changed are written back out exactly as they
+//! were read, including their line endings, and lines that aren't understood
+//! are kept as they are, so loading and saving a file from the Lumatone
+//! editor gives back the same file.
 
-use std::{fs::File, io::{BufRead, BufReader, Write}, path::Path};
+use std::{collections::BTreeSet, fmt, io::{Read, Write}, path::{Path, PathBuf}, str::FromStr, sync::OnceLock};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use regex::Regex;
 
-use super::{KeyIndex, KeyInfo, Keyboard, RGB8};
-
-pub fn load<P: AsRef<Path>>(p: P) -> Result<Keyboard> {
-    let board_re = Regex::new(r"^\[Board(\d+)\]$")?;
-    let key_re = Regex::new(r"^Key_(\d+)=(\d+)$")?;
-    let chan_re = Regex::new(r"^Chan_(\d+)=(\d+)$")?;
-    let col_re = Regex::new(r"^Col_(\d+)=([0-9a-fA-F]{6})$")?;
-    let invert_re = Regex::new(r"^CCInvert_(\d+)$")?;
-
-    // For now, just ignore these, and we will use hard-coded defaults.
-    let ignore_re = Regex::new(r"^(AfterTouchActive|LightOnKeyStrokes|InvertFootController|InvertSustain|ExprCtrlSensivity|VelocityIntrvlTbl|NoteOnOffVelocityCrvTbl|FaderConfig|afterTouchConfig|LumaTouchConfig)=(.*)$")?;
-
-    let mut state = State::default();
-
-    let mut board = Keyboard::default();
-
-    for line in BufReader::new(File::open(p)?).lines() {
-        let line = line?;
-        if let Some(cap) = board_re.captures(&line) {
-            state.set_group(&mut board)?;
-
-            let group = cap.get(1).unwrap().as_str().parse::<usize>()?;
-            state.group = Some(group);
-
-            state.keys = vec![0; 56];
-            state.chans = vec![0; 56];
-            state.cols = vec![RGB8::white(); 56];
-            state.inverts = vec![false; 56];
-            continue;
-        }
-        if let Some(cap) = key_re.captures(&line) {
-            let index = cap.get(1).unwrap().as_str().parse::<usize>()?;
-            let value = cap.get(2).unwrap().as_str().parse::<u8>()?;
-            state.keys[index] = value;
-            continue;
-        }
-        if let Some(cap) = chan_re.captures(&line) {
-            let index = cap.get(1).unwrap().as_str().parse::<usize>()?;
-            let value = cap.get(2).unwrap().as_str().parse::<u8>()?;
-            state.chans[index] = value;
-            continue;
-        }
-        if let Some(cap) = col_re.captures(&line) {
-            let index = cap.get(1).unwrap().as_str().parse::<usize>()?;
-            let value = RGB8::parse(cap.get(2).unwrap().as_str())?;
-            state.cols[index] = value;
-            continue
-        }
-        if let Some(cap) = invert_re.captures(&line) {
-            let index = cap.get(1).unwrap().as_str().parse::<usize>()?;
-            state.inverts[index] = true;
-            continue;
-        }
-        if let Some(_cap) = ignore_re.captures(&line) {
-            continue;
-        }
-        println!("line: {:?}", line);
-        println!("state: {:?}", state);
-        break;
-    }
-    state.set_group(&mut board)?;
-    Ok(board)
-}
-
-#[derive(Debug, Default)]
-struct State {
-    group: Option<usize>,
-    keys: Vec<u8>,
-    chans: Vec<u8>,
-    cols: Vec<RGB8>,
-    inverts: Vec<bool>,
-
-    aftertouch: bool,
-    light_on_strokes: bool,
-    invert_foot: bool,
-    invert_sustain: bool,
-    expr_sensitivity: usize,
-    velocity_intrvl: Vec<u16>,
-    velocity: Vec<u8>,
-    facer: Vec<u8>,
-    after_touch: Vec<u8>,
-    luma_touch: Vec<u8>,
-}
-
-impl State {
-    fn set_group(&mut self, keyb: &mut Keyboard) -> Result<()> {
-        let group = if let Some(group) = self.group {
-            group
-        } else {
-            return Ok(())
-        };
-
-        for key in 0..56 {
-            let channel = self.chans[key];
-            let note = self.keys[key];
-            keyb.set(KeyIndex { group: group as u8, key: key as u8 },
-                     Some(KeyInfo {
-                         channel,
-                         note,
-                         color: self.cols[key],
-                         label: format!("{}:{}", channel, note),
-                     }));
-        }
-        self.group = None;
+use super::{Curve, GlobalSettings, KeyIndex, KeyInfo, KeyType, Keyboard, VelocityIntervals, RGB8};
+
+/// A whole LTN file.
+#[derive(Debug, Clone, Default)]
+pub struct Ltn {
+    lines: Vec<Line>,
+}
+
+/// The fields for a single key.  Fields that aren't in the file are None.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LtnKey {
+    /// The midi note, `Key_n`.
+    pub note: Option<u8>,
+    /// The midi channel, `Chan_n`.
+    pub channel: Option<u8>,
+    /// The key color, `Col_n`.
+    pub color: Option<RGB8>,
+    /// The key type, `KTyp_n`.
+    pub key_type: Option<u8>,
+    /// Is the controller inverted, `CCInvert_n`.
+    pub cc_invert: bool,
+}
+
+/// How to handle problems when reading a file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ParseMode {
+    /// Any problem is an error.
+    Strict,
+    /// Problems are warnings.  Lines that can't be parsed are kept in the file
+    /// as they are, but otherwise ignored, and when something is given more
+    /// than once, the last one is used.
+    Lenient,
+}
+
+/// A problem with a line of an LTN file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError {
+    /// The file, when it was read from one.
+    pub file: Option<PathBuf>,
+    /// The line number, starting from 1.
+    pub line: usize,
+    /// What is wrong with the line.
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone)]
+struct Line {
+    entry: Entry,
+    /// The text the line was read from, kept until the entry is changed.
+    text: Option<String>,
+    /// The line ending, which is empty on a last line without one.
+    end: &'static str,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Entry {
+    /// A `[BoardN]` header, starting the keys of a group.
+    Board(usize),
+    /// A field of a single key.
+    Key(usize, Field),
+    /// A global setting, such as `AfterTouchActive=0`.
+    Setting(String, String),
+    /// Anything else.
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Field {
+    Note(u8),
+    Channel(u8),
+    Color(RGB8),
+    Type(u8),
+    CCInvert,
+}
+
+impl Field {
+    /// The fields of a key, in the order the editor writes them.
+    fn of(key: &LtnKey) -> [Option<Field>; 5] {
+        [
+            key.note.map(Field::Note),
+            key.channel.map(Field::Channel),
+            key.color.map(Field::Color),
+            key.key_type.map(Field::Type),
+            key.cc_invert.then_some(Field::CCInvert),
+        ]
+    }
+
+    /// Is this the same field, ignoring the value.
+    fn same_kind(self, other: Field) -> bool {
+        std::mem::discriminant(&self) == std::mem::discriminant(&other)
+    }
+}
+
+struct Patterns {
+    board: Regex,
+    field: Regex,
+    invert: Regex,
+    setting: Regex,
+}
+
+fn patterns() -> &'static Patterns {
+    static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| Patterns {
+        board: Regex::new(r"^\[Board(\d+)\]$").unwrap(),
+        field: Regex::new(r"^(Key|Chan|Col|KTyp)_(\d+)=(.*)$").unwrap(),
+        invert: Regex::new(r"^CCInvert_(\d+)$").unwrap(),
+        setting: Regex::new(r"^([A-Za-z]+)=(.*)$").unwrap(),
+    })
+}
+
+impl Entry {
+    /// Parse a single line, giving the reason when it can't be.
+    fn parse(line: &str) -> Result<Entry, String> {
+        let pat = patterns();
+        if let Some(cap) = pat.board.captures(line) {
+            return Ok(Entry::Board(number("board", &cap[1])?));
+        }
+        if let Some(cap) = pat.field.captures(line) {
+            let index = number("key", &cap[2])?;
+            let value = &cap[3];
+            let field = match &cap[1] {
+                "Key" => Field::Note(number("note", value)?),
+                "Chan" => Field::Channel(number("channel", value)?),
+                // Older versions of this program wrote the color with a '#'.
+                "Col" => {
+                    let hex = value.strip_prefix('#').unwrap_or(value);
+                    if hex.len() != 6 || !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+                        return Err(format!("invalid color {:?}", value));
+                    }
+                    Field::Color(RGB8::parse(hex).map_err(|e| e.to_string())?)
+                }
+                _ => Field::Type(number("key type", value)?),
+            };
+            return Ok(Entry::Key(index, field));
+        }
+        if let Some(cap) = pat.invert.captures(line) {
+            return Ok(Entry::Key(number("key", &cap[1])?, Field::CCInvert));
+        }
+        if let Some(cap) = pat.setting.captures(line) {
+            return Ok(Entry::Setting(cap[1].to_string(), cap[2].to_string()));
+        }
+        Ok(Entry::Other)
+    }
+}
+
+fn number<T: FromStr>(what: &str, text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("invalid {} {:?}", what, text))
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Board(group) => write!(f, "[Board{}]", group),
+            Entry::Key(key, Field::Note(note)) => write!(f, "Key_{}={}", key, note),
+            Entry::Key(key, Field::Channel(channel)) => write!(f, "Chan_{}={}", key, channel),
+            Entry::Key(key, Field::Color(color)) =>
+                write!(f, "Col_{}={:02X}{:02X}{:02X}", key, color.r, color.g, color.b),
+            Entry::Key(key, Field::Type(ktype)) => write!(f, "KTyp_{}={}", key, ktype),
+            Entry::Key(key, Field::CCInvert) => write!(f, "CCInvert_{}", key),
+            Entry::Setting(name, value) => write!(f, "{}={}", name, value),
+            Entry::Other => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Ltn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match &line.text {
+                Some(text) => write!(f, "{}{}", text, line.end)?,
+                None => write!(f, "{}{}", line.entry, line.end)?,
+            }
+        }
         Ok(())
     }
 }
 
-/// Write out a lumatone file.  This only has the parameters that are meaningful
-/// here.
+impl Ltn {
+    /// Parse the text of an LTN file.  Problems are ignored, as in lenient
+    /// mode.
+    pub fn parse(text: &str) -> Result<Ltn> {
+        Ok(Ltn::parse_with(text, ParseMode::Lenient)?.0)
+    }
+
+    /// Parse the text of an LTN file, rejecting anything that isn't
+    /// understood.
+    pub fn parse_strict(text: &str) -> Result<Ltn> {
+        Ok(Ltn::parse_with(text, ParseMode::Strict)?.0)
+    }
+
+    /// Parse the text of an LTN file.  In lenient mode, the problems are
+    /// returned as warnings, along with the file.
+    pub fn parse_with(text: &str, mode: ParseMode) -> Result<(Ltn, Vec<ParseError>), ParseError> {
+        Ltn::parse_file(text, mode, None)
+    }
+
+    /// Read an LTN file.  Problems are ignored, as in lenient mode.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Ltn> {
+        Ok(Ltn::load_with(path, ParseMode::Lenient)?.0)
+    }
+
+    /// Read an LTN file, rejecting anything that isn't understood.
+    pub fn load_strict<P: AsRef<Path>>(path: P) -> Result<Ltn> {
+        Ok(Ltn::load_with(path, ParseMode::Strict)?.0)
+    }
+
+    /// Read an LTN file.  In lenient mode, the problems are returned as
+    /// warnings, along with the file.
+    pub fn load_with<P: AsRef<Path>>(path: P, mode: ParseMode) -> Result<(Ltn, Vec<ParseError>)> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Ok(Ltn::parse_file(&text, mode, Some(path))?)
+    }
+
+    /// Read an LTN file from the given reader.  In lenient mode, the
+    /// problems are returned as warnings, along with the file.
+    pub fn read_with<R: Read>(mut reader: R, mode: ParseMode) -> Result<(Ltn, Vec<ParseError>)> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok(Ltn::parse_file(&text, mode, None)?)
+    }
+
+    fn parse_file(text: &str, mode: ParseMode, file: Option<&Path>) -> Result<(Ltn, Vec<ParseError>), ParseError> {
+        let mut lines = vec![];
+        let mut warnings = vec![];
+        let mut board = None;
+        let mut boards = BTreeSet::new();
+        let mut fields = BTreeSet::new();
+        let mut settings = BTreeSet::new();
+        for (number, raw) in text.split_inclusive('\n').enumerate() {
+            let (text, end) = if let Some(text) = raw.strip_suffix("\r\n") {
+                (text, "\r\n")
+            } else if let Some(text) = raw.strip_suffix('\n') {
+                (text, "\n")
+            } else {
+                (raw, "")
+            };
+
+            let entry = Entry::parse(text);
+            let mut invalid = false;
+            let problem = match &entry {
+                Err(reason) => Some(reason.clone()),
+                Ok(Entry::Board(group)) => {
+                    board = Some(*group);
+                    if *group >= 5 {
+                        Some(format!("board {} is out of range", group))
+                    } else if !boards.insert(*group) {
+                        Some(format!("board {} is given more than once", group))
+                    } else {
+                        None
+                    }
+                }
+                Ok(Entry::Key(key, _)) => {
+                    let name = text.split(['_', '=']).next().unwrap_or_default();
+                    if *key >= 56 {
+                        Some(format!("key {} is out of range", key))
+                    } else if let Some(board) = board {
+                        if !fields.insert((board, *key, name)) {
+                            Some(format!("{}_{} is given more than once in board {}", name, key, board))
+                        } else {
+                            None
+                        }
+                    } else {
+                        Some(format!("{}_{} is before any board", name, key))
+                    }
+                }
+                Ok(Entry::Setting(name, value)) => {
+                    match apply_setting(&mut GlobalSettings::default(), name, value) {
+                        Err(e) => {
+                            invalid = true;
+                            Some(e.to_string())
+                        }
+                        Ok(false) => Some(format!("unknown setting {}", name)),
+                        Ok(true) if !settings.insert(name.clone()) =>
+                            Some(format!("setting {} is given more than once", name)),
+                        Ok(true) => None,
+                    }
+                }
+                Ok(Entry::Other) if text.trim().is_empty() => None,
+                Ok(Entry::Other) => Some("unrecognized line".to_string()),
+            };
+
+            // Lines that can't be parsed, or settings with invalid values,
+            // are kept as they are, but otherwise ignored.
+            let entry = match entry {
+                Ok(entry) if !invalid => entry,
+                _ => Entry::Other,
+            };
+            if let Some(reason) = problem {
+                let error = ParseError { file: file.map(Path::to_path_buf), line: number + 1, reason };
+                match mode {
+                    ParseMode::Strict => return Err(error),
+                    ParseMode::Lenient => warnings.push(error),
+                }
+            }
+            lines.push(Line { entry, text: Some(text.to_string()), end });
+        }
+        Ok((Ltn { lines }, warnings))
+    }
+
+    /// Write out this LTN file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Write this LTN file to the given writer.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(self.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    /// Build a file with every key on the keyboard, and the keyboard's
+    /// settings.  Keys without a note are written as disabled keys.
+    pub fn from_keyboard(keyb: &Keyboard) -> Ltn {
+        let mut ltn = Ltn::default();
+        ltn.set_keys(keyb);
+        ltn.set_global_settings(&keyb.settings);
+        ltn
+    }
+
+    /// The groups that have a `[BoardN]` section.
+    pub fn groups(&self) -> Vec<u8> {
+        let mut groups: Vec<u8> = self.lines
+            .iter()
+            .filter_map(|line| match line.entry {
+                Entry::Board(group) => u8::try_from(group).ok(),
+                _ => None,
+            })
+            .collect();
+        groups.sort();
+        groups.dedup();
+        groups
+    }
+
+    /// The fields of a single key.  If a field is given more than once, the
+    /// last one is used.
+    pub fn key(&self, index: KeyIndex) -> LtnKey {
+        let mut result = LtnKey::default();
+        for (_, field) in self.key_lines(index) {
+            match field {
+                Field::Note(note) => result.note = Some(note),
+                Field::Channel(channel) => result.channel = Some(channel),
+                Field::Color(color) => result.color = Some(color),
+                Field::Type(ktype) => result.key_type = Some(ktype),
+                Field::CCInvert => result.cc_invert = true,
+            }
+        }
+        result
+    }
+
+    /// Change the fields of a single key.  Lines for fields that are
+    /// unchanged are left alone, fields that are now None are removed, and new
+    /// fields are added after the other fields of the key.
+    pub fn set_key(&mut self, index: KeyIndex, key: &LtnKey) {
+        for field in Field::of(key) {
+            let Some(field) = field else {
+                continue;
+            };
+            let lines: Vec<usize> = self.key_lines(index)
+                .filter(|(_, old)| old.same_kind(field))
+                .map(|(line, _)| line)
+                .collect();
+            match lines.last() {
+                Some(&line) => {
+                    let line = &mut self.lines[line];
+                    if line.entry != Entry::Key(index.key as usize, field) {
+                        line.entry = Entry::Key(index.key as usize, field);
+                        line.text = None;
+                    }
+                }
+                None => {
+                    let at = self.insert_point(index);
+                    self.insert(at, Entry::Key(index.key as usize, field));
+                }
+            }
+        }
+
+        // Remove the fields that are no longer present.
+        let fields = Field::of(key);
+        let remove: Vec<usize> = self.key_lines(index)
+            .filter(|(_, old)| !fields.iter().flatten().any(|field| field.same_kind(*old)))
+            .map(|(line, _)| line)
+            .collect();
+        for line in remove.into_iter().rev() {
+            self.lines.remove(line);
+        }
+    }
+
+    /// Set the fields of every key from the keyboard.  The key type is only
+    /// written for keys that aren't notes, or where the file already has
+    /// one.  Keys without a note are written as disabled keys.
+    pub fn set_keys(&mut self, keyb: &Keyboard) {
+        let disabled = KeyInfo::disabled();
+        for index in KeyIndex::iter_all() {
+            let info = keyb.get(index).unwrap_or(&disabled);
+            let mut key = self.key(index);
+            key.note = Some(info.note);
+            key.channel = Some(info.channel);
+            key.color = Some(info.color);
+            let old_type = key.key_type.and_then(KeyType::from_ltn).unwrap_or_default();
+            if old_type != info.key_type {
+                key.key_type = Some(info.key_type.ltn());
+            }
+            key.cc_invert = info.cc_invert;
+            self.set_key(index, &key);
+        }
+    }
+
+    /// The value of a global setting, such as `AfterTouchActive`.
+    pub fn setting(&self, name: &str) -> Option<&str> {
+        self.lines.iter().rev().find_map(|line| match &line.entry {
+            Entry::Setting(n, value) if n == name => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// All of the global settings, in the order they are in the file.
+    pub fn settings(&self) -> Vec<(&str, &str)> {
+        self.lines
+            .iter()
+            .filter_map(|line| match &line.entry {
+                Entry::Setting(name, value) => Some((name.as_str(), value.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Change a global setting, adding it to the end if it isn't present.
+    pub fn set_setting(&mut self, name: &str, value: &str) {
+        let entry = Entry::Setting(name.to_string(), value.to_string());
+        let found = self.lines.iter().rposition(|line| {
+            matches!(&line.entry, Entry::Setting(n, _) if n == name)
+        });
+        match found {
+            Some(at) => {
+                let line = &mut self.lines[at];
+                if line.entry != entry {
+                    line.entry = entry;
+                    line.text = None;
+                }
+            }
+            None => self.insert(self.lines.len(), entry),
+        }
+    }
+
+    /// The settings for the whole keyboard.  Settings missing from the file
+    /// are given their factory values.
+    pub fn global_settings(&self) -> Result<GlobalSettings> {
+        let mut result = GlobalSettings::default();
+        for (name, value) in self.settings() {
+            apply_setting(&mut result, name, value)?;
+        }
+        Ok(result)
+    }
+
+    /// Change the settings for the whole keyboard.  Settings that are
+    /// unchanged keep the text they were read from.
+    pub fn set_global_settings(&mut self, settings: &GlobalSettings) {
+        let flag = |value: bool| if value { "1" } else { "0" };
+        self.set_setting(AFTERTOUCH_ACTIVE, flag(settings.aftertouch_active));
+        self.set_setting(LIGHT_ON_KEYSTROKES, flag(settings.light_on_keystrokes));
+        self.set_setting(INVERT_FOOT_CONTROLLER, flag(settings.invert_foot_controller));
+        self.set_setting(INVERT_SUSTAIN, flag(settings.invert_sustain));
+        self.set_setting(EXPRESSION_SENSITIVITY, &settings.expression_sensitivity.to_string());
+        self.set_setting(VELOCITY_INTERVALS, &write_table(settings.velocity_intervals.values()));
+        self.set_setting(VELOCITY, &write_table(settings.velocity.values()));
+        self.set_setting(FADER, &write_table(settings.fader.values()));
+        self.set_setting(AFTERTOUCH, &write_table(settings.aftertouch.values()));
+        self.set_setting(LUMATOUCH, &write_table(settings.lumatouch.values()));
+    }
+
+    /// Lines that aren't understood, which are kept as they are.
+    pub fn other_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| line.entry == Entry::Other)
+            .filter_map(|line| line.text.as_deref())
+            .collect()
+    }
+
+    /// Build a keyboard from this file.  Every key of a group that has a
+    /// section is given a note, missing fields being zero, or white.
+    pub fn keyboard(&self) -> Result<Keyboard> {
+        let mut keyb = Keyboard { settings: self.global_settings()?, ..Keyboard::default() };
+        for group in self.groups() {
+            for key in 0..56 {
+                let index = KeyIndex { group, key };
+                if !index.is_valid() {
+                    continue;
+                }
+                let fields = self.key(index);
+                let channel = fields.channel.unwrap_or(0);
+                let note = fields.note.unwrap_or(0);
+                keyb.set(index, Some(KeyInfo {
+                    channel,
+                    note,
+                    color: fields.color.unwrap_or(RGB8::white()),
+                    label: format!("{}:{}", channel, note),
+                    key_type: fields.key_type.and_then(KeyType::from_ltn).unwrap_or_default(),
+                    cc_invert: fields.cc_invert,
+                }));
+            }
+        }
+        Ok(keyb)
+    }
+
+    /// The line numbers and fields of a given key.
+    fn key_lines(&self, index: KeyIndex) -> impl Iterator<Item = (usize, Field)> + '_ {
+        let mut group = None;
+        self.lines.iter().enumerate().filter_map(move |(line, entry)| match entry.entry {
+            Entry::Board(g) => {
+                group = Some(g);
+                None
+            }
+            Entry::Key(key, field) if group == Some(index.group as usize) && key == index.key as usize =>
+                Some((line, field)),
+            _ => None,
+        })
+    }
+
+    /// Where to add a new field for a key.  This is after the last field of
+    /// the key, or if it has none, after the last field of any key in the
+    /// key's group, adding a section for the group if it isn't there.
+    fn insert_point(&mut self, index: KeyIndex) -> usize {
+        if let Some((line, _)) = self.key_lines(index).last() {
+            return line + 1;
+        }
+        let group = index.group as usize;
+        let mut current = None;
+        let mut in_group = None;
+        let mut before = None;
+        for (line, entry) in self.lines.iter().enumerate() {
+            match entry.entry {
+                Entry::Board(g) => {
+                    current = Some(g);
+                    if g == group {
+                        in_group = Some(line + 1);
+                    } else if g < group {
+                        before = Some(line + 1);
+                    }
+                }
+                Entry::Key(..) if current == Some(group) => in_group = Some(line + 1),
+                Entry::Key(..) if current.is_some_and(|g| g < group) => before = Some(line + 1),
+                _ => (),
+            }
+        }
+        if let Some(at) = in_group {
+            return at;
+        }
+        let at = before.unwrap_or(0);
+        self.insert(at, Entry::Board(group));
+        at + 1
+    }
+
+    /// Insert a new line, with the same line ending as the rest of the file.
+    fn insert(&mut self, at: usize, entry: Entry) {
+        let end = self.lines.first().map_or("\n", |line| line.end);
+        let end = if end.is_empty() { "\n" } else { end };
+        // A last line without an ending needs one before anything is added
+        // after it.
+        if at == self.lines.len() {
+            if let Some(last) = self.lines.last_mut() {
+                if last.end.is_empty() {
+                    last.end = end;
+                }
+            }
+        }
+        self.lines.insert(at, Line { entry, text: None, end });
+    }
+}
+
+/// The names of the global settings, in the order the editor writes them.
+/// The expression pedal sensitivity is misspelled in the files.
+const AFTERTOUCH_ACTIVE: &str = "AfterTouchActive";
+const LIGHT_ON_KEYSTROKES: &str = "LightOnKeyStrokes";
+const INVERT_FOOT_CONTROLLER: &str = "InvertFootController";
+const INVERT_SUSTAIN: &str = "InvertSustain";
+const EXPRESSION_SENSITIVITY: &str = "ExprCtrlSensivity";
+const VELOCITY_INTERVALS: &str = "VelocityIntrvlTbl";
+const VELOCITY: &str = "NoteOnOffVelocityCrvTbl";
+const FADER: &str = "FaderConfig";
+const AFTERTOUCH: &str = "afterTouchConfig";
+const LUMATOUCH: &str = "LumaTouchConfig";
+
+/// Set a single global setting from its value in the file.  Returns false
+/// if the setting isn't known.
+fn apply_setting(settings: &mut GlobalSettings, name: &str, value: &str) -> Result<bool> {
+    let text = value.trim();
+    match name {
+        AFTERTOUCH_ACTIVE => settings.aftertouch_active = flag(name, text)?,
+        LIGHT_ON_KEYSTROKES => settings.light_on_keystrokes = flag(name, text)?,
+        INVERT_FOOT_CONTROLLER => settings.invert_foot_controller = flag(name, text)?,
+        INVERT_SUSTAIN => settings.invert_sustain = flag(name, text)?,
+        EXPRESSION_SENSITIVITY => settings.expression_sensitivity = text.parse()
+            .map_err(|_| anyhow!("Invalid value {:?} for {}", text, name))?,
+        VELOCITY_INTERVALS => settings.velocity_intervals = VelocityIntervals::new(&table(name, text)?)?,
+        VELOCITY => settings.velocity = Curve::new(&table(name, text)?)?,
+        FADER => settings.fader = Curve::new(&table(name, text)?)?,
+        AFTERTOUCH => settings.aftertouch = Curve::new(&table(name, text)?)?,
+        LUMATOUCH => settings.lumatouch = Curve::new(&table(name, text)?)?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn flag(name: &str, text: &str) -> Result<bool> {
+    match text {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => bail!("{} should be 0 or 1, not {:?}", name, text),
+    }
+}
+
+fn table<T: FromStr>(name: &str, text: &str) -> Result<Vec<T>> {
+    text.split_whitespace()
+        .map(|value| value.parse().map_err(|_| anyhow!("Invalid value {:?} in {}", value, name)))
+        .collect()
+}
+
+/// Tables are written with a space after every value, as the editor does.
+fn write_table<T: fmt::Display>(values: &[T]) -> String {
+    values.iter().map(|value| format!("{} ", value)).collect()
+}
+
+pub fn load<P: AsRef<Path>>(p: P, mode: ParseMode) -> Result<(Keyboard, Vec<ParseError>)> {
+    let (ltn, warnings) = Ltn::load_with(p, mode)?;
+    Ok((ltn.keyboard()?, warnings))
+}
+
+/// Write out a lumatone file, with the keys and the global settings.
 pub fn save<P: AsRef<Path>>(p: P, keyb: &Keyboard) -> Result<()> {
-    let mut fd = File::create(p)?;
+    Ltn::from_keyboard(keyb).save(p)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{Ltn, LtnKey, ParseError, ParseMode};
+    use crate::lumatone::{Curve, GlobalSettings, KeyIndex, KeyInfo, KeyType, Keyboard, RGB8};
+
+    /// Loading and saving the files from the editor changes nothing.
+    #[test]
+    fn ltn_round_trip() {
+        for entry in std::fs::read_dir("data").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "ltn") {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path).unwrap();
+            let ltn = Ltn::parse(&text).unwrap();
+            assert_eq!(ltn.to_string(), text, "{:?}", path);
+            assert!(ltn.other_lines().is_empty(), "{:?}", path);
+        }
+
+        let ltn = Ltn::load("data/factory-8-31.ltn").unwrap();
+        assert_eq!(ltn.groups(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(ltn.setting("AfterTouchActive"), Some("0"));
+        assert_eq!(ltn.settings().len(), 10);
+        let key = ltn.key(KeyIndex { group: 0, key: 0 });
+        assert_eq!(key.key_type, Some(0));
+        assert!(key.cc_invert);
+    }
 
-    let default_info = KeyInfo::default();
+    /// Changing a key only changes the lines for that key.
+    #[test]
+    fn ltn_edit() {
+        let text = std::fs::read_to_string("data/factory-2-harmonic-table.ltn").unwrap();
+        let mut ltn = Ltn::parse(&text).unwrap();
+        let index = KeyIndex { group: 1, key: 5 };
+        let mut key = ltn.key(index);
+        key.note = Some(99);
+        key.key_type = Some(1);
+        key.cc_invert = false;
+        ltn.set_key(index, &key);
+        assert_eq!(ltn.key(index), key);
 
-    for group in 0..5 {
-        writeln!(&mut fd, "[Board{}]", group)?;
-        for key in 0..56 {
-            let info = keyb.get(KeyIndex { group: group as u8, key: key as u8 }).unwrap_or(&default_info);
-            writeln!(&mut fd, "Key_{}={}", key, info.note)?;
-            writeln!(&mut fd, "Chan_{}={}", key, info.channel)?;
-            writeln!(&mut fd, "Col_{}={}", key, info.color.to_hex())?;
+        // Nothing else changed.
+        let orig = Ltn::parse(&text).unwrap();
+        let back = Ltn::parse(&ltn.to_string()).unwrap();
+        for other in KeyIndex::iter_all().filter(|&other| other != index) {
+            assert_eq!(back.key(other), orig.key(other));
         }
+        assert_eq!(back.key(index), key);
+        assert_eq!(back.settings(), orig.settings());
+        assert!(ltn.to_string().contains("\r\nKey_5=99\r\nChan_5="));
+        assert_eq!(ltn.to_string().lines().count(), text.lines().count());
+
+        ltn.set_setting("AfterTouchActive", "1");
+        ltn.set_setting("Extra", "2");
+        assert_eq!(ltn.setting("AfterTouchActive"), Some("1"));
+        assert!(ltn.to_string().ends_with("\r\nExtra=2\r\n"));
+    }
+
+    /// The settings in the files from the editor are the factory settings.
+    #[test]
+    fn ltn_settings() {
+        let mut ltn = Ltn::load("data/lumatone_Wicki-Hayden_v3.ltn").unwrap();
+        let mut settings = ltn.global_settings().unwrap();
+        assert!(settings.aftertouch_active);
+        assert!(settings.light_on_keystrokes);
+        settings.aftertouch_active = false;
+        settings.light_on_keystrokes = false;
+        assert_eq!(settings, GlobalSettings::default());
+
+        let before = ltn.to_string();
+        ltn.set_global_settings(&ltn.global_settings().unwrap());
+        assert_eq!(ltn.to_string(), before);
+
+        settings.velocity = Curve::soft();
+        ltn.set_global_settings(&settings);
+        let back = Ltn::parse(&ltn.to_string()).unwrap().keyboard().unwrap();
+        assert_eq!(back.settings, settings);
+        assert_eq!(ltn.to_string().lines().count(), before.lines().count());
+
+        // Tables with the wrong number of entries are rejected, or kept as
+        // they are but ignored.
+        let err = Ltn::parse_strict("FaderConfig=1 2 3 \n").unwrap_err();
+        assert_eq!(err.to_string(), "1: Curve has 3 entries, instead of 128");
+        let ltn = Ltn::parse("FaderConfig=1 2 3 \n").unwrap();
+        assert_eq!(ltn.global_settings().unwrap(), GlobalSettings::default());
+        assert_eq!(ltn.other_lines(), vec!["FaderConfig=1 2 3 "]);
+        let mut ltn = Ltn::default();
+        ltn.set_setting("AfterTouchActive", "yes");
+        assert!(ltn.keyboard().is_err());
+    }
+
+    /// Problems are reported with the file and line, and only stop the file
+    /// being read in strict mode.
+    #[test]
+    fn ltn_errors() {
+        let err = Ltn::load_strict("data/19-edo Godzilla9 singlechannel.ltn").unwrap_err();
+        let err = err.downcast::<ParseError>().unwrap();
+        assert_eq!(err.file.as_deref(), Some(Path::new("data/19-edo Godzilla9 singlechannel.ltn")));
+        assert_eq!(err.line, 846);
+        assert_eq!(err.to_string(),
+                   "data/19-edo Godzilla9 singlechannel.ltn:846: Key_55 is given more than once in board 4");
+
+        let (ltn, warnings) = Ltn::load_with("data/19-edo Godzilla9 singlechannel.ltn", ParseMode::Lenient).unwrap();
+        assert_eq!(warnings.len(), 18);
+        assert_eq!(ltn.key(KeyIndex { group: 4, key: 55 }).note, Some(125));
+        assert!(Keyboard::load("data/19-edo Godzilla9 singlechannel.ltn").is_ok());
+        assert!(Keyboard::load_strict("data/19-edo Godzilla9 singlechannel.ltn").is_err());
+
+        let text = "Key_0=1\n[Board0]\nKey_56=1\nChan_1=300\nCol_2=red\nwhat\n\n\
+                    [Board7]\n[Board0]\nFaderConfig=1\nNewSetting=1\nAfterTouchActive=0\nAfterTouchActive=1\n";
+        let reasons = [
+            (1, "Key_0 is before any board"),
+            (3, "key 56 is out of range"),
+            (4, "invalid channel \"300\""),
+            (5, "invalid color \"red\""),
+            (6, "unrecognized line"),
+            (8, "board 7 is out of range"),
+            (9, "board 0 is given more than once"),
+            (10, "Curve has 1 entries, instead of 128"),
+            (11, "unknown setting NewSetting"),
+            (13, "setting AfterTouchActive is given more than once"),
+        ];
+        assert_eq!(Ltn::parse_with(text, ParseMode::Strict).unwrap_err().line, 1);
+        assert!(Ltn::parse_strict(text).is_err());
+        assert_eq!(Ltn::parse(text).unwrap().to_string(), text);
+        let (ltn, warnings) = Ltn::parse_with(text, ParseMode::Lenient).unwrap();
+        let found: Vec<(usize, &str)> = warnings.iter().map(|w| (w.line, w.reason.as_str())).collect();
+        assert_eq!(found, reasons);
+        assert_eq!(ltn.to_string(), text);
+
+        // What could be read is still used.
+        let keyb = ltn.keyboard().unwrap();
+        assert!(keyb.settings.aftertouch_active);
+        assert_eq!(keyb.get(KeyIndex::origin()).unwrap().note, 0);
+        assert_eq!(ltn.key(KeyIndex { group: 0, key: 1 }).channel, None);
+    }
+
+    /// A keyboard written out reads back the same.
+    #[test]
+    fn ltn_keyboard() {
+        let mut keyb = Keyboard::default();
+        keyb.fill_reference();
+        keyb.keys[2][7].as_mut().unwrap().color = RGB8::new(0xc0, 0x41, 0xc0);
+        let mut sustain = KeyInfo::controller(1, 64);
+        sustain.cc_invert = true;
+        keyb.reserve(KeyIndex { group: 4, key: 55 }, sustain);
+        keyb.reserve(KeyIndex { group: 4, key: 54 }, KeyInfo::disabled());
+        let ltn = Ltn::from_keyboard(&keyb);
+        let text = ltn.to_string();
+        assert!(text.contains("\nCol_7=C041C0\n"));
+        assert!(text.contains("\nKey_54=0\nChan_54=1\nCol_54=000000\nKTyp_54=4\n\
+                                Key_55=64\nChan_55=1\nCol_55=FFFFFF\nKTyp_55=2\nCCInvert_55\n"));
+        assert_eq!(text.matches("KTyp").count(), 2);
+        assert!(text.contains("\nCCInvert_55\nAfterTouchActive=0\nLightOnKeyStrokes=0\nInvertFootController=0\n\
+                                InvertSustain=0\nExprCtrlSensivity=0\nVelocityIntrvlTbl=1 2 3 4 "));
+
+        let back = Ltn::parse(&text).unwrap().keyboard().unwrap();
+        for index in KeyIndex::iter_all() {
+            let (a, b) = (keyb.get(index).unwrap(), back.get(index).unwrap());
+            assert_eq!((a.channel, a.note, a.color, a.key_type, a.cc_invert),
+                       (b.channel, b.note, b.color, b.key_type, b.cc_invert));
+        }
+
+        // Keys that weren't filled are disabled, rather than notes on
+        // channel 0.
+        let text = Ltn::from_keyboard(&Keyboard::default()).to_string();
+        assert_eq!(text.matches("KTyp").count(), 280);
+        assert_eq!(text.matches("=4\n").count(), 280);
+        assert!(!text.lines().any(|line| line.starts_with("Chan_") && line.ends_with("=0")));
+
+        // Key types already in a file are kept.
+        let mut ltn = Ltn::load("data/lumatone_Wicki-Hayden_v3.ltn").unwrap();
+        let keyb = ltn.keyboard().unwrap();
+        assert_eq!(keyb.get(KeyIndex::origin()).unwrap().key_type, KeyType::LumaTouch);
+        let before = ltn.to_string();
+        ltn.set_keys(&keyb);
+        assert_eq!(ltn.to_string(), before);
+
+        // Files written with a '#' before the color can still be read.
+        let ltn = Ltn::parse("[Board0]\nCol_3=#c041c0\n").unwrap();
+        assert_eq!(ltn.key(KeyIndex { group: 0, key: 3 }), LtnKey {
+            color: Some(RGB8::new(0xc0, 0x41, 0xc0)),
+            ..LtnKey::default()
+        });
     }
-    Ok(())
 }
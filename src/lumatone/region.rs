@@ -0,0 +1,178 @@
+//! Fill regions.
+//!
+//! A region is a set of keys that a fill is allowed to fill in.  The simple
+//! regions pick keys by board, row, column, or diagonal, or inside a polygon,
+//! and these can be combined, to make diagonal splits, a bass strip along the
+//! bottom edge, or several zones with different layouts.
+//!
+//! Positions are given as axial hex coordinates, see `coord`.
+
+use super::{Dir, Hex, KeyIndex};
+
+/// A set of keys to fill.
+#[derive(Debug, Copy, Clone)]
+pub enum Region<'a> {
+    /// Every key on the keyboard.
+    All,
+    /// The boards (groups) from `first` to `last`, inclusive.
+    Boards { first: u8, last: u8 },
+    /// The rows from `first` to `last`, inclusive, counting down from 0 at the
+    /// top.
+    Rows { first: isize, last: isize },
+    /// Columns either side of the starting key of the fill.  The columns
+    /// zigzag vertically, so this covers all of the rows.
+    Columns { left: usize, right: usize },
+    /// A band of diagonals running in the direction `dir`, from `first` to
+    /// `last` inclusive.  Up-left diagonals are numbered by `q`, and up-right
+    /// diagonals by `q + r`, both going up to the right.  Left and right
+    /// "diagonals" are the rows.
+    Diagonals { dir: Dir, first: isize, last: isize },
+    /// The keys inside, or on the edge of, the polygon with these corners.
+    Polygon(&'a [Hex]),
+    /// Keys that are in any of these regions.
+    Union(&'a [Region<'a>]),
+    /// Keys that are in all of these regions.
+    Intersection(&'a [Region<'a>]),
+    /// Keys in the first region, but not in the second.
+    Difference(&'a Region<'a>, &'a Region<'a>),
+}
+
+impl Region<'_> {
+    /// Is this key part of the region.  The start is the starting key of the
+    /// fill, which some regions are relative to.  Keys that aren't on the
+    /// keyboard are never part of a region.
+    pub fn contains(&self, key: KeyIndex, start: KeyIndex) -> bool {
+        let Some(hex) = key.hex() else {
+            return false;
+        };
+        match *self {
+            Region::All => true,
+            Region::Boards { first, last } => (first..=last).contains(&key.group),
+            Region::Rows { first, last } => (first..=last).contains(&hex.r),
+            Region::Columns { left, right } => {
+                let Some(start) = start.hex() else {
+                    return false;
+                };
+                let column = column(hex, start);
+                -(left as isize) <= column && column <= right as isize
+            }
+            Region::Diagonals { dir, first, last } => {
+                let index = match dir {
+                    Dir::UpLeft | Dir::DownRight => hex.q,
+                    Dir::UpRight | Dir::DownLeft => hex.q + hex.r,
+                    Dir::Left | Dir::Right => hex.r,
+                };
+                (first..=last).contains(&index)
+            }
+            Region::Polygon(corners) => in_polygon(hex, corners),
+            Region::Union(regions) => regions.iter().any(|region| region.contains(key, start)),
+            Region::Intersection(regions) => regions.iter().all(|region| region.contains(key, start)),
+            Region::Difference(a, b) => a.contains(key, start) && !b.contains(key, start),
+        }
+    }
+
+    /// All of the keys in this region.
+    pub fn keys(&self, start: KeyIndex) -> Vec<KeyIndex> {
+        KeyIndex::iter_all().filter(|&key| self.contains(key, start)).collect()
+    }
+}
+
+/// The zigzag column of a position, relative to the start.  Going straight up
+/// from the start alternates between up-left and up-right moves, starting
+/// with up-left, and going down starts with down-left.
+fn column(hex: Hex, start: Hex) -> isize {
+    let dr = hex.r - start.r;
+    hex.q - start.q - (-dr).div_euclid(2)
+}
+
+/// Is this position inside, or on the edge of, the polygon.  Containment is
+/// unchanged by the skew of the axial coordinates, so they can be used
+/// directly.
+fn in_polygon(hex: Hex, corners: &[Hex]) -> bool {
+    let (x, y) = (hex.q, hex.r);
+    let mut inside = false;
+    for (i, a) in corners.iter().enumerate() {
+        let b = corners[(i + 1) % corners.len()];
+        // On the edge.
+        let cross = (b.q - a.q) * (y - a.r) - (b.r - a.r) * (x - a.q);
+        if cross == 0
+            && (a.q.min(b.q)..=a.q.max(b.q)).contains(&x)
+            && (a.r.min(b.r)..=a.r.max(b.r)).contains(&y)
+        {
+            return true;
+        }
+        // Count crossings of a ray to the right.
+        if (a.r > y) != (b.r > y) {
+            let dy = b.r - a.r;
+            let cross = (b.q - a.q) * (y - a.r) - (x - a.q) * dy;
+            if (cross > 0) == (dy > 0) {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod test {
+    use super::Region;
+    use crate::lumatone::{Anchor, Dir, FillInfo, Hex, KeyIndex, Keyboard, WICKI_HAYDEN};
+    use crate::tuning::EDO12;
+
+    #[test]
+    fn region_all() {
+        assert_eq!(Region::All.keys(KeyIndex::origin()).len(), 280);
+    }
+
+    #[test]
+    fn region_shapes() {
+        let start = KeyIndex { group: 2, key: 27 };
+        assert_eq!(Region::Boards { first: 1, last: 2 }.keys(start).len(), 112);
+
+        // A bass strip along the bottom edge, and the rest above it.
+        let bass = Region::Rows { first: 16, last: 18 };
+        let rest = Region::Difference(&Region::All, &bass);
+        let bass_keys = bass.keys(start).len();
+        assert!(bass_keys > 0);
+        assert_eq!(rest.keys(start).len() + bass_keys, 280);
+
+        // A diagonal split covers everything exactly once.
+        let low = Region::Diagonals { dir: Dir::UpRight, first: isize::MIN, last: 15 };
+        let high = Region::Diagonals { dir: Dir::UpRight, first: 16, last: isize::MAX };
+        let low_keys = low.keys(start);
+        assert!(!low_keys.is_empty());
+        assert_eq!(low_keys.len() + high.keys(start).len(), 280);
+        assert!(low_keys.iter().all(|key| !high.contains(*key, start)));
+
+        // A triangle, with its corners and edges included.
+        let triangle = Region::Polygon(&[Hex::new(0, 0), Hex::new(4, 0), Hex::new(0, 4)]);
+        let keys: Vec<Hex> = triangle.keys(start).iter().filter_map(|key| key.hex()).collect();
+        assert!(keys.contains(&Hex::new(0, 0)));
+        assert!(keys.contains(&Hex::new(2, 2)));
+        assert!(!keys.contains(&Hex::new(3, 2)));
+
+        let both = Region::Union(&[bass, Region::Boards { first: 0, last: 0 }]);
+        assert!(both.contains(KeyIndex::origin(), start));
+        let neither = Region::Intersection(&[bass, Region::Boards { first: 0, last: 0 }]);
+        assert!(neither.keys(start).is_empty());
+    }
+
+    #[test]
+    fn region_fill() {
+        // A bass strip along the bottom, starting at C2, below the rest of
+        // the keyboard.
+        let bass = Region::Rows { first: 15, last: 18 };
+        let top = Region::Difference(&Region::All, &bass);
+        let mut keyb = Keyboard::default();
+        let fill = FillInfo::new(KeyIndex { group: 2, key: 27 }, top);
+        let upper = keyb.fill_layout(&EDO12, &WICKI_HAYDEN, &fill).unwrap();
+        let lower = keyb.fill_layout(&EDO12, &WICKI_HAYDEN, &FillInfo {
+            start: KeyIndex { group: 3, key: 51 },
+            anchor: Anchor::Name("C2"),
+            region: bass,
+        }).unwrap();
+        assert_eq!(upper.filled.len() + lower.filled.len(), 280);
+        assert!(lower.filled.iter().all(|&key| key.hex().unwrap().r >= 15));
+        assert_eq!(keyb.get(KeyIndex { group: 3, key: 51 }).unwrap().note, 36);
+    }
+}
@@ -0,0 +1,207 @@
+//! Isomorphic layout search.
+//!
+//! Rather than guessing at generators, try every consistent combination of
+//! steps along the three axes for an EDO, and rank them.  Each key is given a
+//! position on the hex lattice, with the right axis and the up-right axis as
+//! the basis, so the note on a key is just a combination of the steps along
+//! those two axes.  That makes it cheap to measure a candidate without filling
+//! in a keyboard.
+
+use std::{collections::BTreeSet, fmt};
+
+use crate::tuning::{Edo, Interval, IntervalDirection, IntervalStep, Tuning};
+
+use super::{KeyIndex, Layout};
+
+/// How far across the lattice to look for an interval.  This is further than
+/// any hand can stretch.
+const REACH: isize = 8;
+
+/// Layouts covering less than this many octaves, or more than the maximum,
+/// are penalized.  Beyond the maximum, the extra range is past what can be
+/// heard or sent as midi, and costs closer keys.
+const MIN_RANGE: f64 = 4.0;
+const MAX_RANGE: f64 = 9.0;
+
+/// The chord tones that should be close together.
+const CHORD_TONES: [IntervalStep; 3] = [
+    IntervalStep::MinorThird,
+    IntervalStep::MajorThird,
+    IntervalStep::PerfectFifth,
+];
+
+/// A single layout found by the search, along with the measurements used to
+/// rank it.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// The number of steps along the right, up-left, and up-right axes.
+    pub steps: [isize; 3],
+    /// The range from the lowest to the highest key, in octaves.
+    pub range: f64,
+    /// The shortest move across the keyboard that is an octave, as (right,
+    /// up-right) moves, and the number of keys it crosses.
+    pub octave: Option<((isize, isize), usize)>,
+    /// The average number of keys that play each note.
+    pub duplicates: f64,
+    /// The total number of keys crossed to reach a minor third, major third,
+    /// and perfect fifth up.  None if any of them can't be reached.
+    pub chord: Option<usize>,
+    /// The combined cost, lower is better.
+    pub cost: f64,
+}
+
+impl Candidate {
+    /// Return a layout with these steps.
+    pub fn layout(&self) -> Layout {
+        let [right, up_left, up_right] = self.steps.map(interval);
+        Layout::new(right, up_left, up_right)
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [right, up_left, up_right] = self.steps;
+        write!(f, "steps right {}, up-left {}, up-right {}: ", right, up_left, up_right)?;
+        write!(f, "range {:.2} octaves, {:.2} keys per note", self.range, self.duplicates)?;
+        match self.octave {
+            Some(((r, u), keys)) => write!(f, ", octave {} keys ({} right, {} up-right)", keys, r, u)?,
+            None => write!(f, ", no octave within reach")?,
+        }
+        match self.chord {
+            Some(keys) => write!(f, ", chord tones {} keys", keys)?,
+            None => write!(f, ", chord tones out of reach")?,
+        }
+        writeln!(f, ", cost {:.1}", self.cost)?;
+        writeln!(f, "Layout::new(")?;
+        for steps in self.steps {
+            let dir = if steps < 0 { "Down" } else { "Up" };
+            writeln!(f, "    Interval::steps({}, IntervalDirection::{}),", steps.abs(), dir)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Search for layouts for the given EDO.  Every combination where the keys
+/// go up in pitch to the right, and that reaches every pitch class in every
+/// octave, is returned, best first.
+pub fn search(edo: &Edo) -> Vec<Candidate> {
+    let period = edo.octave() as isize;
+    let coords = key_coords();
+    let chord: Vec<isize> = CHORD_TONES.iter().map(|&step| edo.get_steps(step)).collect();
+
+    let mut result = vec![];
+    for up_left in -(period - 1)..period {
+        for up_right in -(period - 1)..period {
+            let right = up_right - up_left;
+            if right <= 0 || right >= period {
+                continue;
+            }
+            let layout = Layout::new(interval(right), interval(up_left), interval(up_right));
+            let Ok(check) = layout.validate(edo) else {
+                continue;
+            };
+            if !check.is_valid() {
+                continue;
+            }
+            result.push(measure(&coords, [right, up_left, up_right], period, &chord));
+        }
+    }
+    result.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+    result
+}
+
+/// Measure a single candidate.
+fn measure(coords: &[(isize, isize)], steps: [isize; 3], period: isize, chord: &[isize]) -> Candidate {
+    let [right, _, up_right] = steps;
+    let notes: Vec<isize> = coords.iter().map(|&(r, u)| r * right + u * up_right).collect();
+    let low = *notes.iter().min().unwrap();
+    let high = *notes.iter().max().unwrap();
+    let range = (high - low) as f64 / period as f64;
+    let distinct: BTreeSet<isize> = notes.iter().cloned().collect();
+    let duplicates = notes.len() as f64 / distinct.len() as f64;
+
+    let octave = shortest(right, up_right, period);
+    let chord = chord
+        .iter()
+        .map(|&target| shortest(right, up_right, target).map(|(_, keys)| keys))
+        .sum::<Option<usize>>();
+
+    // The weights are rough, the measurements are printed so the ranking can
+    // be second guessed.
+    let mut cost = chord.map_or(100.0, |keys| keys as f64);
+    cost += octave.map_or(100.0, |(_, keys)| keys as f64 / 2.0);
+    cost += duplicates - 1.0;
+    cost += 2.0 * (MIN_RANGE - range).max(0.0);
+    cost += 2.0 * (range - MAX_RANGE).max(0.0);
+
+    Candidate { steps, range, octave, duplicates, chord, cost }
+}
+
+/// Find the shortest move across the lattice that goes up by the given number
+/// of steps, preferring moves that go to the right, then up.
+fn shortest(right: isize, up_right: isize, target: isize) -> Option<((isize, isize), usize)> {
+    let mut best: Option<((isize, isize), usize)> = None;
+    for r in (-REACH..=REACH).rev() {
+        for u in (-REACH..=REACH).rev() {
+            if r * right + u * up_right != target {
+                continue;
+            }
+            let keys = hex_distance(r, u);
+            if best.is_none_or(|(_, best)| keys < best) {
+                best = Some(((r, u), keys));
+            }
+        }
+    }
+    best
+}
+
+/// The number of keys crossed by moving `r` keys right and `u` keys
+/// up-right.
+fn hex_distance(r: isize, u: isize) -> usize {
+    r.unsigned_abs().max(u.unsigned_abs()).max((r + u).unsigned_abs())
+}
+
+/// The lattice position of every key, as (right, up-right) moves from the
+/// top key.
+fn key_coords() -> Vec<(isize, isize)> {
+    KeyIndex::iter_all()
+        .filter_map(|key| {
+            let hex = key.hex()?;
+            Some((hex.q + hex.r, -hex.r))
+        })
+        .collect()
+}
+
+/// An interval of the given number of steps, going down if negative.
+fn interval(steps: isize) -> Interval {
+    let dir = if steps < 0 { IntervalDirection::Down } else { IntervalDirection::Up };
+    Interval::steps(steps.abs(), dir)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{key_coords, search};
+    use crate::tuning::{Edo, EDO12, EDO31};
+
+    #[test]
+    fn search_coords() {
+        let coords = key_coords();
+        assert_eq!(coords.len(), 280);
+    }
+
+    #[test]
+    fn search_edo() {
+        // The harmonic table is the most compact for 12-EDO.
+        let found = search(&EDO12);
+        assert_eq!(found[0].steps, [4, 3, 7]);
+        assert!(found.iter().all(|c| c.steps[0] > 0));
+
+        // Wicki-Hayden is the best for 31-EDO, the harmonic table doesn't
+        // reach every note.
+        let found = search(&EDO31);
+        assert_eq!(found[0].steps, [5, 13, 18]);
+        assert!(!found.iter().any(|c| c.steps == [10, 8, 18]));
+
+        assert!(!search(&Edo::new(7).unwrap()).is_empty());
+    }
+}
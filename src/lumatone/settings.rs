@@ -0,0 +1,229 @@
+//! Global Lumatone settings.
+//!
+//! Besides the keys, an LTN file has settings for the whole keyboard: a few
+//! switches, and tables giving the response to how fast, or how hard, the
+//! keys are pressed.  These default to the settings the Lumatone ships with.
+
+use anyhow::{bail, Result};
+
+/// The number of entries in a response curve.
+pub const CURVE_LEN: usize = 128;
+
+/// The number of entries in the velocity interval table.
+pub const VELOCITY_INTERVALS_LEN: usize = 127;
+
+/// The settings for the whole keyboard.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GlobalSettings {
+    /// Send aftertouch from the keys.
+    pub aftertouch_active: bool,
+    /// Light up keys as they are pressed.
+    pub light_on_keystrokes: bool,
+    /// Invert the expression pedal.
+    pub invert_foot_controller: bool,
+    /// Invert the sustain pedal.
+    pub invert_sustain: bool,
+    /// The sensitivity of the expression pedal.
+    pub expression_sensitivity: u8,
+    /// The timing table used to turn the speed of a key press into a
+    /// velocity.
+    pub velocity_intervals: VelocityIntervals,
+    /// The note on and off velocity curve.
+    pub velocity: Curve,
+    /// The curve for controller keys.
+    pub fader: Curve,
+    /// The aftertouch curve.
+    pub aftertouch: Curve,
+    /// The LumaTouch curve.
+    pub lumatouch: Curve,
+}
+
+impl Default for GlobalSettings {
+    fn default() -> Self {
+        GlobalSettings {
+            aftertouch_active: false,
+            light_on_keystrokes: false,
+            invert_foot_controller: false,
+            invert_sustain: false,
+            expression_sensitivity: 0,
+            velocity_intervals: VelocityIntervals::FACTORY,
+            velocity: Curve::FACTORY_VELOCITY,
+            fader: Curve::FACTORY_FADER,
+            aftertouch: Curve::FACTORY_AFTERTOUCH,
+            lumatouch: Curve::FACTORY_LUMATOUCH,
+        }
+    }
+}
+
+/// A response curve, giving the midi value sent for each level of input.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Curve([u8; CURVE_LEN]);
+
+impl Curve {
+    /// Build a curve from a table of values, which must have an entry for
+    /// every input level, each no more than 127.
+    pub fn new(values: &[u8]) -> Result<Curve> {
+        let Ok(table) = <[u8; CURVE_LEN]>::try_from(values) else {
+            bail!("Curve has {} entries, instead of {}", values.len(), CURVE_LEN);
+        };
+        if let Some(value) = table.iter().find(|&&value| value > 127) {
+            bail!("Curve value {} is out of range", value);
+        }
+        Ok(Curve(table))
+    }
+
+    /// Every value of the curve.
+    pub fn values(&self) -> &[u8; CURVE_LEN] {
+        &self.0
+    }
+
+    /// The output for a given input level.
+    pub fn get(&self, input: u8) -> u8 {
+        self.0[input as usize & 0x7f]
+    }
+
+    /// The output is the same as the input.
+    pub fn linear() -> Curve {
+        Curve::power(1.0)
+    }
+
+    /// Louder for a lighter touch.
+    pub fn soft() -> Curve {
+        Curve::power(0.5)
+    }
+
+    /// Needs a heavier touch to get loud.
+    pub fn hard() -> Curve {
+        Curve::power(2.0)
+    }
+
+    /// A curve where the output is the input raised to the given power, both
+    /// scaled to 0 to 1.
+    pub fn power(exponent: f64) -> Curve {
+        Curve(std::array::from_fn(|input| {
+            (127.0 * (input as f64 / 127.0).powf(exponent)).round() as u8
+        }))
+    }
+
+    /// Look up a curve by name: "linear", "soft", "hard", or one of the
+    /// factory curves, "factory-velocity", "factory-fader",
+    /// "factory-aftertouch", and "factory-lumatouch".
+    pub fn preset(name: &str) -> Option<Curve> {
+        match name {
+            "linear" => Some(Curve::linear()),
+            "soft" => Some(Curve::soft()),
+            "hard" => Some(Curve::hard()),
+            "factory-velocity" => Some(Curve::FACTORY_VELOCITY),
+            "factory-fader" => Some(Curve::FACTORY_FADER),
+            "factory-aftertouch" => Some(Curve::FACTORY_AFTERTOUCH),
+            "factory-lumatouch" => Some(Curve::FACTORY_LUMATOUCH),
+            _ => None,
+        }
+    }
+
+    /// The factory note on and off velocity curve.
+    pub const FACTORY_VELOCITY: Curve = Curve([
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2,
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
+        19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34,
+        35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50,
+        51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 63, 64, 65, 66, 68,
+        69, 70, 72, 73, 74, 76, 77, 79, 80, 82, 84, 85, 87, 88, 90, 92,
+        94, 96, 97, 99, 101, 103, 105, 108, 110, 112, 114, 117, 119, 121, 124, 127,
+    ]);
+
+    /// The factory curve for controller keys.
+    pub const FACTORY_FADER: Curve = Curve([
+        1, 2, 2, 2, 3, 3, 3, 4, 4, 4, 5, 5, 6, 6, 6, 7,
+        7, 7, 8, 8, 9, 9, 9, 10, 10, 10, 11, 11, 12, 12, 12, 13,
+        13, 14, 14, 14, 15, 15, 16, 16, 17, 17, 17, 18, 18, 19, 19, 20,
+        20, 20, 21, 21, 22, 22, 23, 23, 24, 24, 25, 25, 26, 26, 27, 27,
+        28, 28, 29, 29, 30, 31, 31, 32, 32, 33, 33, 34, 35, 35, 36, 37,
+        37, 38, 39, 39, 40, 41, 41, 42, 43, 44, 45, 45, 46, 47, 48, 49,
+        50, 51, 52, 53, 55, 56, 57, 59, 62, 65, 68, 71, 74, 77, 79, 82,
+        85, 88, 91, 94, 97, 99, 102, 105, 108, 111, 114, 117, 119, 122, 125, 127,
+    ]);
+
+    /// The factory aftertouch curve.
+    pub const FACTORY_AFTERTOUCH: Curve = Curve([
+        0, 2, 3, 5, 6, 8, 9, 10, 12, 13, 14, 16, 17, 18, 20, 21,
+        22, 24, 25, 26, 27, 28, 30, 31, 32, 33, 34, 36, 37, 38, 39, 40,
+        41, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 57, 58,
+        59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 70, 71, 72, 73,
+        74, 75, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 85, 86, 87, 88,
+        89, 90, 91, 92, 92, 93, 94, 95, 96, 97, 98, 99, 99, 100, 101, 102,
+        103, 104, 104, 105, 106, 107, 108, 108, 109, 110, 111, 112, 112, 113, 114, 115,
+        116, 116, 117, 118, 119, 120, 120, 121, 122, 123, 123, 124, 125, 126, 126, 127,
+    ]);
+
+    /// The factory LumaTouch curve.
+    pub const FACTORY_LUMATOUCH: Curve = Curve([
+        0, 1, 2, 2, 3, 3, 3, 4, 4, 4, 5, 5, 5, 6, 6, 7,
+        7, 7, 8, 8, 8, 9, 9, 10, 10, 10, 11, 11, 11, 12, 12, 13,
+        13, 13, 14, 14, 15, 15, 15, 16, 16, 17, 17, 18, 18, 18, 19, 19,
+        20, 20, 21, 21, 22, 22, 22, 23, 23, 24, 24, 25, 25, 26, 26, 27,
+        27, 28, 28, 29, 29, 30, 30, 31, 32, 32, 33, 33, 34, 34, 35, 36,
+        36, 37, 37, 38, 39, 39, 40, 41, 41, 42, 43, 43, 44, 45, 46, 47,
+        47, 48, 49, 50, 51, 52, 53, 53, 54, 56, 57, 58, 60, 61, 63, 65,
+        68, 70, 73, 75, 78, 81, 84, 87, 90, 94, 98, 102, 107, 113, 121, 127,
+    ]);
+}
+
+/// The timing table used to turn the speed of a key press into a velocity.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct VelocityIntervals([u16; VELOCITY_INTERVALS_LEN]);
+
+impl VelocityIntervals {
+    /// Build the table, which must have every entry.
+    pub fn new(values: &[u16]) -> Result<VelocityIntervals> {
+        let Ok(table) = <[u16; VELOCITY_INTERVALS_LEN]>::try_from(values) else {
+            bail!("Velocity interval table has {} entries, instead of {}",
+                  values.len(), VELOCITY_INTERVALS_LEN);
+        };
+        Ok(VelocityIntervals(table))
+    }
+
+    /// Every value of the table.
+    pub fn values(&self) -> &[u16; VELOCITY_INTERVALS_LEN] {
+        &self.0
+    }
+
+    /// The factory table.
+    pub const FACTORY: VelocityIntervals = VelocityIntervals([
+        1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+        17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32,
+        33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48,
+        49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 60, 61, 62, 63, 64, 66,
+        67, 68, 70, 71, 72, 73, 74, 76, 77, 79, 81, 82, 84, 86, 88, 90,
+        92, 94, 96, 98, 101, 104, 107, 111, 115, 119, 124, 129, 134, 140, 146, 152,
+        159, 170, 171, 175, 180, 185, 190, 195, 200, 205, 210, 215, 220, 225, 230, 235,
+        240, 245, 250, 255, 260, 265, 270, 275, 280, 285, 290, 295, 300, 305, 310,
+    ]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Curve, VelocityIntervals, CURVE_LEN};
+
+    #[test]
+    fn settings_curves() {
+        for name in ["linear", "soft", "hard", "factory-velocity", "factory-fader",
+                     "factory-aftertouch", "factory-lumatouch"] {
+            let curve = Curve::preset(name).unwrap();
+            assert!(curve.values().windows(2).all(|pair| pair[0] <= pair[1]), "{}", name);
+            assert_eq!(curve.get(127), 127, "{}", name);
+        }
+        assert_eq!(Curve::linear().get(64), 64);
+        assert!(Curve::soft().get(64) > 64);
+        assert!(Curve::hard().get(64) < 64);
+        assert_eq!(Curve::preset("loud"), None);
+
+        assert!(Curve::new(&[0; CURVE_LEN]).is_ok());
+        assert!(Curve::new(&[0; CURVE_LEN - 1]).is_err());
+        assert!(Curve::new(&[128; CURVE_LEN]).is_err());
+        assert!(VelocityIntervals::new(&[1; 128]).is_err());
+        assert_eq!(VelocityIntervals::new(VelocityIntervals::FACTORY.values()).unwrap(),
+                   VelocityIntervals::FACTORY);
+    }
+}
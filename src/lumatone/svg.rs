@@ -2,7 +2,7 @@
 
 use anyhow::Result;
 use svg::{node::element::{path::Data, Path, Style, Text}, Document};
-use std::f32::consts;
+use std::{f32::consts, io::Write};
 
 use super::RGB8;
 
@@ -39,6 +39,22 @@ impl SvgOut {
     }
 
     pub fn save<P: AsRef<std::path::Path>>(&self, p: P) -> Result<()> {
+        svg::save(p, &self.document())?;
+        Ok(())
+    }
+
+    /// Write the SVG to the given writer.
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        svg::write(writer, &self.document())?;
+        Ok(())
+    }
+
+    /// The text of the SVG.
+    pub fn text(&self) -> String {
+        self.document().to_string()
+    }
+
+    fn document(&self) -> Document {
         let mut document = Document::new()
             .set("viewBox", (-20, -20, 36.0 * SPACING, 12.0 * SPACING));
 
@@ -54,8 +70,7 @@ impl SvgOut {
             document = document.add(label.clone());
         }
 
-        svg::save(p, &document)?;
-        Ok(())
+        document
     }
 
     /// Generate a path element for a basic hexagon.
@@ -0,0 +1,139 @@
+//! Layout validation.
+//!
+//! An isomorphic layout only makes sense if the three axes agree with each
+//! other.  Moving up-right, and then back down-left along the up-left axis
+//! lands one key to the right, so the right interval must be the difference of
+//! the other two.  When it isn't, the keys that get filled in depend on the
+//! order of the fill.
+//!
+//! A consistent layout may still only reach some of the notes of the tuning.
+//! The steps along the axes generate a subgroup, every `stride` steps, so the
+//! harmonic table in 31-EDO, for example, only reaches every other step.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+
+use crate::tuning::{Interval, Tuning};
+
+use super::Layout;
+
+/// What was found when checking a layout against a tuning.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Validation {
+    /// The number of steps along the right, up-left, and up-right axes.
+    pub steps: [isize; 3],
+    /// Is the right axis the difference of the up-right and up-left axes.
+    pub consistent: bool,
+    /// The layout reaches every this many steps of the tuning.
+    pub stride: usize,
+    /// The number of steps in the period of the tuning, if it has one.
+    pub period: Option<usize>,
+}
+
+impl Validation {
+    /// Is this a layout that reaches every step of the tuning, the same way
+    /// regardless of how the keyboard is filled.
+    pub fn is_valid(&self) -> bool {
+        self.consistent && self.stride == 1
+    }
+
+    /// The number of pitch classes (steps within the period) that the layout
+    /// reaches.
+    pub fn pitch_classes(&self) -> Option<usize> {
+        let period = self.period?;
+        Some(period / gcd(period, self.stride))
+    }
+
+    /// The number of periods between repeats of a pitch class.  This is 1 when
+    /// every pitch class is available in every period.
+    pub fn period_stride(&self) -> Option<usize> {
+        let period = self.period?;
+        Some(self.stride / gcd(period, self.stride))
+    }
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [right, up_left, up_right] = self.steps;
+        write!(f, "steps right {}, up-left {}, up-right {}", right, up_left, up_right)?;
+        if !self.consistent {
+            write!(f, ", inconsistent: up-right - up-left is {}", up_right - up_left)?;
+        }
+        if self.stride > 1 {
+            write!(f, ", only reaches every {} steps", self.stride)?;
+            if let (Some(classes), Some(periods)) = (self.pitch_classes(), self.period_stride()) {
+                write!(f, " ({} of {} pitch classes", classes, self.period.unwrap())?;
+                if periods > 1 {
+                    write!(f, ", each every {} periods", periods)?;
+                }
+                write!(f, ")")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Layout {
+    /// Check this layout against a tuning.  This is only an error if the
+    /// tuning can't resolve one of the intervals of the layout.
+    pub fn validate(&self, tuning: &dyn Tuning) -> Result<Validation> {
+        let steps = |interval: Interval| -> Result<isize> {
+            let steps = tuning.interval_steps(interval.size())
+                .ok_or_else(|| anyhow!("Tuning can't resolve interval {:?}", interval))?;
+            Ok(if interval.is_up() { steps } else { -steps })
+        };
+        let steps = [steps(self.right)?, steps(self.up_left)?, steps(self.up_right)?];
+        let [right, up_left, up_right] = steps;
+
+        let stride = steps
+            .iter()
+            .fold(0, |acc, step| gcd(acc, step.unsigned_abs()));
+
+        Ok(Validation {
+            steps,
+            consistent: right == up_right - up_left,
+            stride,
+            period: tuning.period_steps(),
+        })
+    }
+}
+
+/// The greatest common divisor, where gcd(0, n) is n.
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lumatone::{Layout, BOSANQUET, HARMONIC_TABLE, WICKI_HAYDEN};
+    use crate::tuning::{Interval, IntervalDirection, IntervalStep, EDO12, EDO31, EDO53};
+
+    #[test]
+    fn validate_layouts() {
+        for layout in [&WICKI_HAYDEN, &HARMONIC_TABLE, &BOSANQUET] {
+            assert!(layout.validate(&EDO12).unwrap().is_valid());
+        }
+        let check = WICKI_HAYDEN.validate(&EDO53).unwrap();
+        assert_eq!(check.steps, [9, 22, 31]);
+        assert!(check.is_valid());
+
+        // The harmonic table in 31-EDO only reaches every other step, so each
+        // note is only in every other octave.
+        let check = HARMONIC_TABLE.validate(&EDO31).unwrap();
+        assert!(check.consistent);
+        assert_eq!(check.stride, 2);
+        assert_eq!(check.pitch_classes(), Some(31));
+        assert_eq!(check.period_stride(), Some(2));
+        assert!(!check.is_valid());
+
+        let broken = Layout::new(
+            Interval::new(IntervalStep::MajorThird, IntervalDirection::Up),
+            Interval::new(IntervalStep::PerfectFourth, IntervalDirection::Up),
+            Interval::new(IntervalStep::PerfectFifth, IntervalDirection::Up),
+        );
+        let check = broken.validate(&EDO12).unwrap();
+        assert!(!check.consistent);
+        assert_eq!(check.to_string(), "steps right 4, up-left 5, up-right 7, inconsistent: up-right - up-left is 2");
+    }
+}
@@ -1,68 +1,46 @@
 use std::fs::create_dir;
 
-use anyhow::Result;
-use microtone::{lumatone::{FillInfo, KeyIndex, Keyboard, Layout, BOSANQUET, HARMONIC_TABLE, WICKI_HAYDEN}, tuning::{Tuning, EDO12, EDO17, EDO19, EDO31, EDO41, EDO53}};
+use anyhow::{bail, Result};
+use microtone::{lumatone::{search, FillInfo, KeyIndex, Keyboard, Layout, ParseMode, Region, BOSANQUET, HARMONIC_TABLE, WICKI_HAYDEN}, tuning::{anamark, mts, Edo, Reference, EDO12, EDO17, EDO19, EDO31, EDO41, EDO53, MIDDLE_C_HZ}};
 
 // An ltn to generate.  For each, we generate an ltn, and a svg showing the
-// layout.
+// layout, along with Scala scale and keyboard mapping files, AnaMark tuning
+// files, and MTS sysex files for the tuning, and a report of which pitch
+// classes the keys cover.
 struct Ltn {
     /// The tuning to use for this Ltn.
-    tuning: &'static (dyn Tuning + Sync),
+    tuning: &'static Edo,
     /// Layout
     layout: &'static Layout,
     /// How to fill in the keys.
-    fills: &'static [FillInfo],
+    fills: &'static [FillInfo<'static>],
     /// The base of the filename.
     name: &'static str,
 }
 
 // Various fills.
-static SPLIT_FILL: &'static [FillInfo] = &[
-    FillInfo {
-        left: 8,
-        right: 9,
-        start: KeyIndex { group: 3, key: 47 },
-    },
-    FillInfo {
-        left: 9,
-        right: 9,
-        start: KeyIndex { group: 1, key: 14 },
-    },
+static SPLIT_FILL: &[FillInfo<'static>] = &[
+    FillInfo::new(KeyIndex { group: 3, key: 47 }, Region::Columns { left: 8, right: 9 }),
+    FillInfo::new(KeyIndex { group: 1, key: 14 }, Region::Columns { left: 9, right: 9 }),
 ];
 
 // Similar to the SPLIT_FILL above, but with everything shifted to the left so
 // that sharps are easier to get to.
-static SPLIT_FILL_SHARP: &'static [FillInfo] = &[
-    FillInfo {
-        left: 6,
-        right: 10,
-        start: KeyIndex { group: 3, key: 38 },
-    },
-    FillInfo {
-        left: 5,
-        right: 11,
-        start: KeyIndex { group: 0, key: 24 },
-    },
+static SPLIT_FILL_SHARP: &[FillInfo<'static>] = &[
+    FillInfo::new(KeyIndex { group: 3, key: 38 }, Region::Columns { left: 6, right: 10 }),
+    FillInfo::new(KeyIndex { group: 0, key: 24 }, Region::Columns { left: 5, right: 11 }),
 ];
 
-static WIDE_FILL: &'static [FillInfo] = &[
-    FillInfo {
-        left: 16,
-        right: 16,
-        start: KeyIndex { group: 2, key: 39 },
-    },
+static WIDE_FILL: &[FillInfo<'static>] = &[
+    FillInfo::new(KeyIndex { group: 2, key: 39 }, Region::Columns { left: 16, right: 16 }),
 ];
 
-static WIDE_FILL_DN1: &'static [FillInfo] = &[
-    FillInfo {
-        left: 16,
-        right: 16,
-        start: KeyIndex { group: 2, key: 27 },
-    },
+static WIDE_FILL_DN1: &[FillInfo<'static>] = &[
+    FillInfo::new(KeyIndex { group: 2, key: 27 }, Region::Columns { left: 16, right: 16 }),
 ];
 
 // All of the supported LTNS.
-static LTNS: &'static [Ltn] = &[
+static LTNS: &[Ltn] = &[
     Ltn {
         tuning: &EDO12,
         fills: SPLIT_FILL,
@@ -180,10 +158,39 @@ static LTNS: &'static [Ltn] = &[
 ];
 
 fn main() -> Result<()> {
-    // For now, just generate a keyboard, to view the SVG.
-    // let test = Keyboard::load("data/lumatone_Wicki-Hayden_v3.ltn")?;
-    // let test = Keyboard::load("data/factory-2-harmonic-table.ltn")?;
-    // test.write_svg("test-wh.svg")?;
+    // Any files given on the command line are analyzed instead, to see how
+    // they were built.
+    let files: Vec<String> = std::env::args().skip(1).collect();
+
+    // `search N [count]` looks for good layouts for N-EDO.  N is a val, so
+    // "22b" searches 22-EDO with its second best fifth.
+    if files.first().map(String::as_str) == Some("search") {
+        let Some(size) = files.get(1) else {
+            bail!("Usage: search <edo> [count]");
+        };
+        let count = match files.get(2) {
+            Some(count) => count.parse()?,
+            None => 10,
+        };
+        for candidate in search(&Edo::from_val(size)?).iter().take(count) {
+            println!("{}\n", candidate);
+        }
+        return Ok(());
+    }
+
+    if !files.is_empty() {
+        for file in &files {
+            let (keyb, warnings) = Keyboard::load_with(file, ParseMode::Lenient)?;
+            for warning in &warnings {
+                eprintln!("warning: {}", warning);
+            }
+            match keyb.analyze() {
+                Some(analysis) => println!("{}: {}", file, analysis),
+                None => println!("{}: no neighbouring keys to compare", file),
+            }
+        }
+        return Ok(());
+    }
 
     let _ = create_dir("layouts");
 
@@ -194,14 +201,57 @@ fn main() -> Result<()> {
 
     // Generate all of the layouts.
     for ltn in LTNS {
+        // Flag layouts that don't make sense in their tuning.  An inconsistent
+        // layout would depend on the fill order, so isn't generated at all.
+        let check = ltn.layout.validate(ltn.tuning)?;
+        if !check.consistent {
+            eprintln!("warning: skipping {}: {}", ltn.name, check);
+            continue;
+        }
+        if !check.is_valid() {
+            eprintln!("note: {}: {}", ltn.name, check);
+        }
+
         let mut keyb = Keyboard::default();
+        let mut regions = vec![];
         for fill in ltn.fills {
-            keyb.fill_layout(ltn.tuning, ltn.layout, fill);
+            let result = keyb.fill_layout(ltn.tuning, ltn.layout, fill)?;
+            // Splits are expected to disagree where the fills meet, but keys
+            // left empty are worth knowing about.
+            if !result.dropped.is_empty() {
+                eprintln!("note: {}: {}", ltn.name, result);
+            }
+            regions.push(result.filled);
         }
 
         let _ = create_dir(format!("layouts/{}", ltn.name));
         keyb.write_svg(format!("layouts/{}/{}.svg", ltn.name, ltn.name))?;
         keyb.write_ltn(format!("layouts/{}/{}.ltn", ltn.name, ltn.name))?;
+        if let Some(coverage) = keyb.coverage(ltn.tuning, &regions) {
+            coverage.write_text(format!("layouts/{}/{}-coverage.txt", ltn.name, ltn.name))?;
+            coverage.write_json(format!("layouts/{}/{}-coverage.json", ltn.name, ltn.name))?;
+        }
+        ltn.tuning.write_scl(format!("layouts/{}/{}.scl", ltn.name, ltn.name))?;
+        let reference = Reference::middle_c(ltn.tuning, MIDDLE_C_HZ);
+        ltn.tuning.write_kbm(format!("layouts/{}/{}.kbm", ltn.name, ltn.name), &reference)?;
+
+        // AnaMark tunings, a single .tun file when only one channel is used,
+        // otherwise a scale for each channel.
+        let channels = keyb.channels();
+        if let [channel] = channels[..] {
+            anamark::write_tun(format!("layouts/{}/{}.tun", ltn.name, ltn.name),
+                               ltn.tuning, &reference, channel, ltn.name)?;
+        }
+        anamark::write_msf(format!("layouts/{}/{}.msf", ltn.name, ltn.name),
+                           ltn.tuning, &reference, ltn.name)?;
+
+        // A bulk dump for each channel, and a scale/octave message when the
+        // tuning allows it.
+        mts::write_syx(format!("layouts/{}/{}.syx", ltn.name, ltn.name),
+                       ltn.tuning, &reference, &channels, ltn.name)?;
+        if let Some(message) = mts::scale_octave(ltn.tuning, &reference, &channels) {
+            std::fs::write(format!("layouts/{}/{}-octave.syx", ltn.name, ltn.name), message)?;
+        }
     }
 
     Ok(())
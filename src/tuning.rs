@@ -3,8 +3,26 @@
 //! Manage tuning systems, and the various ways that they deal with names of
 //! notes, and midi note/channel numbers.
 
+use std::{fmt::{self, Write}, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+
 use crate::lumatone::RGB8;
 
+pub use self::encoding::{Blocks, ChannelOctaves, Encoding, MidiEncoding, Sequential};
+pub use self::equal::EqualSteps;
+pub use self::names::ups_and_downs;
+pub use self::scala::ScalaTuning;
+pub use self::temperament::Temperament;
+
+pub mod anamark;
+mod encoding;
+mod equal;
+pub mod mts;
+mod names;
+mod scala;
+mod temperament;
+
 #[derive(Copy, Clone, Default, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub struct MidiNote {
     pub channel: u8,
@@ -24,6 +42,109 @@ pub enum IntervalStep {
     AugmentedFourth,
     DimishedFifth,
     PerfectFifth,
+    MinorSixth,
+    MajorSixth,
+    MinorSeventh,
+    MajorSeventh,
+    Octave,
+}
+
+impl IntervalStep {
+    /// The just intonation size of this interval, in cents.  Tunings that
+    /// aren't built from a chain of fifths use this to find the scale degree
+    /// closest to each interval.
+    pub fn just_cents(self) -> f64 {
+        let (num, den) = match self {
+            IntervalStep::AugUnison => (25.0, 24.0),
+            IntervalStep::MinorSecond => (16.0, 15.0),
+            IntervalStep::MajorSecond => (9.0, 8.0),
+            IntervalStep::MinorThird => (6.0, 5.0),
+            IntervalStep::MajorThird => (5.0, 4.0),
+            IntervalStep::PerfectFourth => (4.0, 3.0),
+            IntervalStep::AugmentedFourth => (45.0, 32.0),
+            IntervalStep::DimishedFifth => (64.0, 45.0),
+            IntervalStep::PerfectFifth => (3.0, 2.0),
+            IntervalStep::MinorSixth => (8.0, 5.0),
+            IntervalStep::MajorSixth => (5.0, 3.0),
+            IntervalStep::MinorSeventh => (9.0, 5.0),
+            IntervalStep::MajorSeventh => (15.0, 8.0),
+            IntervalStep::Octave => (2.0, 1.0),
+        };
+        ratio_cents(num / den)
+    }
+
+    /// This interval as a number of fifths, and a number of octaves.
+    pub fn vector(self) -> (isize, isize) {
+        match self {
+            IntervalStep::AugUnison => (7, -4),
+            IntervalStep::MinorSecond => (-5, 3),
+            IntervalStep::MajorSecond => (2, -1),
+            IntervalStep::MinorThird => (-3, 2),
+            IntervalStep::MajorThird => (4, -2),
+            IntervalStep::PerfectFourth => (-1, 1),
+            IntervalStep::AugmentedFourth => (6, -3),
+            IntervalStep::DimishedFifth => (-6, 4),
+            IntervalStep::PerfectFifth => (1, 0),
+            IntervalStep::MinorSixth => (-4, 3),
+            IntervalStep::MajorSixth => (3, -1),
+            IntervalStep::MinorSeventh => (-2, 2),
+            IntervalStep::MajorSeventh => (5, -2),
+            IntervalStep::Octave => (0, 1),
+        }
+    }
+}
+
+/// The size of an interval.  Besides the named intervals, an interval can be
+/// given as a raw number of steps of the tuning, or as a number of fifths and
+/// octaves, for intervals that don't have a name here.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IntervalSize {
+    /// One of the named intervals.
+    Step(IntervalStep),
+    /// A number of steps of the tuning, such as 7 for the neutral third of
+    /// 24-EDO.  What a step means depends on the tuning.
+    Steps(isize),
+    /// A number of fifths and a number of octaves, such as 2 fifths down an
+    /// octave for a major second.
+    Vector { fifths: isize, octaves: isize },
+}
+
+impl IntervalSize {
+    /// This interval as a number of fifths, and a number of octaves.  Raw steps
+    /// don't have a vector.
+    pub fn vector(self) -> Option<(isize, isize)> {
+        match self {
+            IntervalSize::Step(step) => Some(step.vector()),
+            IntervalSize::Steps(_) => None,
+            IntervalSize::Vector { fifths, octaves } => Some((fifths, octaves)),
+        }
+    }
+
+    /// The just intonation size of this interval, in cents.  Vectors use a
+    /// pure 3/2 fifth.  Raw steps don't have a just size.
+    pub fn just_cents(self) -> Option<f64> {
+        match self {
+            IntervalSize::Step(step) => Some(step.just_cents()),
+            IntervalSize::Steps(_) => None,
+            IntervalSize::Vector { fifths, octaves } => {
+                Some(fifths as f64 * ratio_cents(1.5) + octaves as f64 * 1200.0)
+            }
+        }
+    }
+}
+
+impl From<IntervalStep> for IntervalSize {
+    fn from(step: IntervalStep) -> IntervalSize {
+        IntervalSize::Step(step)
+    }
+}
+
+/// The frequency of middle C, in Hz, when A4 is tuned to 440 Hz in 12-EDO.
+pub const MIDDLE_C_HZ: f64 = 261.625_565_300_598_6;
+
+/// Convert a frequency ratio to cents.
+pub fn ratio_cents(ratio: f64) -> f64 {
+    1200.0 * ratio.log2()
 }
 
 /// Which direction does an interval move in?
@@ -42,16 +163,31 @@ impl IntervalDirection {
     }
 }
 
-/// An Interval itself is a step and direction.
+/// An Interval itself is a size and direction.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct Interval {
-    step: IntervalStep,
+    size: IntervalSize,
     direction: IntervalDirection,
 }
 
 impl Interval {
     pub const fn new(step: IntervalStep, direction: IntervalDirection) -> Interval {
-        Interval { step, direction }
+        Interval { size: IntervalSize::Step(step), direction }
+    }
+
+    /// An interval of a raw number of steps of the tuning.
+    pub const fn steps(steps: isize, direction: IntervalDirection) -> Interval {
+        Interval { size: IntervalSize::Steps(steps), direction }
+    }
+
+    /// An interval of some number of fifths and octaves.
+    pub const fn vector(fifths: isize, octaves: isize, direction: IntervalDirection) -> Interval {
+        Interval { size: IntervalSize::Vector { fifths, octaves }, direction }
+    }
+
+    /// The size of this interval, without the direction.
+    pub fn size(self) -> IntervalSize {
+        self.size
     }
 
     /// Is this an "up" interval.
@@ -62,7 +198,7 @@ impl Interval {
     /// Flip this interval, so that up and down are reversed.
     pub fn flip(self) -> Interval {
         Interval {
-            step: self.step,
+            size: self.size,
             direction: self.direction.flip(),
         }
     }
@@ -87,47 +223,350 @@ pub trait Tuning {
     /// `interval` method.
     fn get_steps(&self, interval: IntervalStep) -> isize;
 
+    /// Resolve any size of interval to a number of steps, the same kind of
+    /// steps as `get_steps`.  None indicates that this kind of interval can't
+    /// be resolved by this tuning.  By default, only the named intervals and
+    /// raw steps are understood.
+    fn interval_steps(&self, size: IntervalSize) -> Option<isize> {
+        match size {
+            IntervalSize::Step(step) => Some(self.get_steps(step)),
+            IntervalSize::Steps(steps) => Some(steps),
+            IntervalSize::Vector { .. } => None,
+        }
+    }
+
     /// Guess a good color for this particular note.
     fn color(&self, note: MidiNote, sharp: bool) -> RGB8;
 
     /// Return middle C for this tuning.
     fn middle_c(&self) -> MidiNote;
+
+    /// The pitch of this note, in cents above middle C.  None indicates that
+    /// this note isn't used by the tuning.
+    fn cents(&self, note: MidiNote) -> Option<f64>;
+
+    /// The number of steps from middle C of this note, the same kind of steps
+    /// as `get_steps`.  None indicates that this note isn't used by the
+    /// tuning, or that the tuning doesn't count its notes in steps.
+    fn steps(&self, _note: MidiNote) -> Option<isize> {
+        None
+    }
+
+    /// The number of steps in the period, for tunings where the pattern of
+    /// steps repeats.  None if there is no period, or the steps from
+    /// `get_steps` aren't steps of a scale.
+    fn period_steps(&self) -> Option<usize> {
+        None
+    }
+
+    /// Find the note with the given name, such as "A4", with either spelling
+    /// of enharmonic notes.  None if no note of the tuning has that name.
+    fn note_named(&self, name: &str) -> Option<MidiNote> {
+        (0..=16)
+            .flat_map(|channel| (0..128).map(move |note| MidiNote { channel, note }))
+            .filter(|&note| self.cents(note).is_some())
+            .find(|&note| self.name(note, true) == name || self.name(note, false) == name)
+    }
+
+    /// The pitch of this note, in cents above the reference note.
+    fn cents_from(&self, note: MidiNote, reference: &Reference) -> Option<f64> {
+        Some(self.cents(note)? - self.cents(reference.note)?)
+    }
+
+    /// The frequency of this note, in Hz, when tuned to the given reference.
+    fn frequency(&self, note: MidiNote, reference: &Reference) -> Option<f64> {
+        let cents = self.cents_from(note, reference)?;
+        Some(reference.frequency * (cents / 1200.0).exp2())
+    }
+}
+
+/// A reference pitch, giving the frequency of a single note of a tuning.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Reference {
+    /// The note that the frequency is given for.
+    pub note: MidiNote,
+    /// The frequency of that note, in Hz.
+    pub frequency: f64,
+}
+
+impl Reference {
+    /// Tune middle C of this tuning to the given frequency.
+    pub fn middle_c(tuning: &dyn Tuning, frequency: f64) -> Reference {
+        Reference { note: tuning.middle_c(), frequency }
+    }
+
+    /// Tune the note with the given name, such as "A4", to the given
+    /// frequency.  Returns None if the tuning doesn't have a note by that name.
+    pub fn named(tuning: &dyn Tuning, name: &str, frequency: f64) -> Option<Reference> {
+        let note = tuning.note_named(name)?;
+        Some(Reference { note, frequency })
+    }
+
+    /// The usual concert pitch, A4 tuned to 440 Hz.  Tunings without an A4
+    /// tune middle C to the frequency it has in 12-EDO.
+    pub fn concert(tuning: &dyn Tuning) -> Reference {
+        Reference::named(tuning, "A4", 440.0)
+            .unwrap_or_else(|| Reference::middle_c(tuning, MIDDLE_C_HZ))
+    }
 }
 
 /// A general Equal division of the octave.
+#[derive(Clone)]
 pub struct Edo {
     /// Number of steps in an octave.
     octave: usize,
-    /// Does this Edo use the channel number as the octave. None indicates no,
-    /// and Some(n) indicates yes, with 'n' as the note number bias. C in a
-    /// given octave will be this note number, with the rest of the octave above
-    /// that.
-    channel_octaves: Option<usize>,
-    /// Middle C.
-    middle_c: MidiNote,
-
-    /// The values of the intervals for this Edo.
-    intervals: &'static [isize],
-
-    /// Names of the pitches, with sharp bias.
-    sharp_names: &'static [&'static str],
-    flat_names: &'static [&'static str],
+    /// How the steps are sent as midi notes.
+    encoding: Encoding,
+
+    /// The number of steps in the fifth.  All of the intervals are derived
+    /// from this, as a chain of fifths.
+    fifth: usize,
+
+    /// Names of the pitches.
+    names: Names,
+}
+
+/// How an Edo names the pitches within an octave.
+#[derive(Clone)]
+enum Names {
+    /// Hand written names, with a sharp bias, and a flat bias.
+    Table {
+        sharp: &'static [&'static str],
+        flat: &'static [&'static str],
+    },
+    /// Names generated from the fifth, using ups and downs notation.
+    UpsDowns,
+}
+
+impl Edo {
+    /// Construct an Edo of the given size, using the fifth that comes closest
+    /// to just.  Notes are named with ups and downs.  Each octave is placed on
+    /// its own midi channel, with middle C on channel 4.  Fails if the octave
+    /// won't fit in a midi channel, or is too small to have a fifth.
+    pub fn new(octave: usize) -> Result<Edo> {
+        let fifth = (octave as f64 * 1.5_f64.log2()).round() as usize;
+        Edo::with_fifth(octave, fifth)
+    }
+
+    /// Construct an Edo of the given size, with an explicit number of steps for
+    /// the fifth.  The fifth has to be larger than the unison, and smaller than
+    /// the octave.
+    pub fn with_fifth(octave: usize, fifth: usize) -> Result<Edo> {
+        if octave == 0 || octave > 128 {
+            bail!("Edo of {} doesn't fit in a midi channel", octave);
+        }
+        if fifth == 0 || fifth >= octave {
+            bail!("Fifth of {} doesn't fit in {}-EDO", fifth, octave);
+        }
+        Ok(Edo {
+            octave,
+            encoding: Encoding::ChannelOctaves(ChannelOctaves::new(octave)),
+            fifth,
+            names: Names::UpsDowns,
+        })
+    }
+
+    /// Construct an Edo from a val in wart notation, such as "17" or "22b".
+    /// Each 'b' selects the next best mapping of the 3rd harmonic, which
+    /// changes the fifth.  Since the intervals are all derived from the chain
+    /// of fifths, warts for the higher primes ('c' for 5, 'd' for 7, and so on)
+    /// wouldn't change the tuning, so they are rejected.
+    pub fn from_val(val: &str) -> Result<Edo> {
+        let digits = val.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(val.len());
+        let octave = val[..digits].parse::<usize>()
+            .map_err(|_| anyhow!("Invalid val: {:?}", val))?;
+        if octave == 0 || octave > 128 {
+            bail!("Edo of {} doesn't fit in a midi channel", octave);
+        }
+
+        let mut b_warts = 0;
+        for ch in val[digits..].chars() {
+            match ch.to_ascii_lowercase() {
+                'a' => bail!("Val {:?} doesn't map the octave to {} steps", val, octave),
+                'b' => b_warts += 1,
+                'c'..='q' => bail!("Wart {:?} in val {:?} isn't supported, only 'b' warts are", ch, val),
+                _ => bail!("Invalid wart {:?} in val {:?}", ch, val),
+            }
+        }
+
+        // The mappings of 3 alternate around the just value, ordered by how far
+        // they are from it.
+        let exact = octave as f64 * 3_f64.log2();
+        let mut below = exact.floor() as isize;
+        let mut above = below + 1;
+        let mut twelfth = 0;
+        for _ in 0..=b_warts {
+            if exact - below as f64 <= above as f64 - exact {
+                twelfth = below;
+                below -= 1;
+            } else {
+                twelfth = above;
+                above += 1;
+            }
+        }
+
+        let fifth = twelfth - octave as isize;
+        if fifth <= 0 || fifth >= octave as isize {
+            bail!("Val {:?} doesn't have a usable fifth", val);
+        }
+        Edo::with_fifth(octave, fifth as usize)
+    }
+
+    /// The number of steps in an octave.
+    pub fn octave(&self) -> usize {
+        self.octave
+    }
+
+    /// The number of steps in the fifth.
+    pub fn fifth(&self) -> usize {
+        self.fifth
+    }
+
+    /// Send the notes of this Edo with a different midi encoding.  This can be
+    /// used with a clone of one of the statics, to target a different synth.
+    /// Fails if the encoding can't hold middle C of this Edo.
+    pub fn with_encoding<E: Into<Encoding>>(self, encoding: E) -> Result<Edo> {
+        let encoding = encoding.into().check(self.octave)?;
+        Ok(Edo { encoding, ..self })
+    }
+
+    /// How the steps of this Edo are sent as midi notes.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// The number of steps in the augmented unison, the difference between a
+    /// sharp and a natural.  This is negative when the fifth is flat enough
+    /// that sharps lower the pitch, and zero in 7-EDO.
+    fn apotome(&self) -> isize {
+        7 * self.fifth as isize - 4 * self.octave as isize
+    }
+
+    /// Return the name for the given step within the octave.
+    fn pitch_name(&self, pitch: usize, sharp: bool) -> String {
+        match self.names {
+            Names::Table { sharp: sharp_names, flat: flat_names } => {
+                let names = if sharp { sharp_names } else { flat_names };
+                names[pitch].to_string()
+            }
+            Names::UpsDowns => {
+                // The sharp hint asks for names that raise the pitch, which
+                // are the flats when the apotome goes down.
+                let sharp = sharp == (self.apotome() >= 0);
+                ups_and_downs(self.octave, self.fifth, pitch, sharp)
+            }
+        }
+    }
+}
+
+// Scala scale and keyboard mapping files.
+impl Edo {
+    /// Generate the text of a Scala `.scl` file describing this tuning.
+    pub fn scl(&self) -> String {
+        let mut out = String::new();
+        self.write_scl_text(&mut out).expect("Writing to a String can't fail");
+        out
+    }
+
+    fn write_scl_text(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "! {}-edo.scl", self.octave)?;
+        writeln!(out, "!")?;
+        writeln!(out, "{} equal divisions of 2/1", self.octave)?;
+        writeln!(out, " {}", self.octave)?;
+        writeln!(out, "!")?;
+        for step in 1..self.octave {
+            writeln!(out, " {:.6}", 1200.0 * step as f64 / self.octave as f64)?;
+        }
+        writeln!(out, " 2/1")
+    }
+
+    /// Generate the text of a Scala `.kbm` keyboard mapping matching the midi
+    /// notes used by this tuning, tuned to the given reference.  The frequency
+    /// in the mapping is always given for middle C.
+    ///
+    /// When the notes use more than one channel, the mapping describes a
+    /// single channel, the one holding middle C.  The other channels are
+    /// expected to be the same map, transposed to match.  Fails if the
+    /// reference note isn't part of this tuning.
+    pub fn kbm(&self, reference: &Reference) -> Result<String> {
+        let Some(frequency) = self.frequency(self.middle_c(), reference) else {
+            bail!("Reference note {:?} is not part of {}-EDO", reference.note, self.octave);
+        };
+        let mut out = String::new();
+        self.write_kbm_text(&mut out, frequency).expect("Writing to a String can't fail");
+        Ok(out)
+    }
+
+    /// Write the `.kbm` text, with middle C tuned to `frequency`.
+    fn write_kbm_text(&self, out: &mut String, frequency: f64) -> fmt::Result {
+        let middle_c = self.middle_c();
+        let (first, last) = match self.encoding {
+            Encoding::ChannelOctaves(enc) => (enc.bias as usize, enc.bias as usize + self.octave - 1),
+            _ => (0, 127),
+        };
+        let middle = middle_c.note;
+
+        writeln!(out, "! {}-edo.kbm", self.octave)?;
+        match self.encoding {
+            Encoding::Sequential(_) => (),
+            Encoding::ChannelOctaves(_) => {
+                writeln!(out, "!")?;
+                writeln!(out, "! Each midi channel is a single octave, with middle C on channel {}.",
+                         middle_c.channel)?;
+            }
+            Encoding::Blocks(_) => {
+                writeln!(out, "!")?;
+                writeln!(out, "! The notes continue across midi channels, with middle C on channel {}.",
+                         middle_c.channel)?;
+            }
+        }
+        writeln!(out, "!")?;
+        writeln!(out, "! Size of map. The pattern repeats every so many keys:")?;
+        writeln!(out, "{}", self.octave)?;
+        writeln!(out, "! First MIDI note number to retune:")?;
+        writeln!(out, "{}", first)?;
+        writeln!(out, "! Last MIDI note number to retune:")?;
+        writeln!(out, "{}", last)?;
+        writeln!(out, "! Middle note where the first entry of the mapping is mapped to:")?;
+        writeln!(out, "{}", middle)?;
+        writeln!(out, "! Reference note for which frequency is given:")?;
+        writeln!(out, "{}", middle)?;
+        writeln!(out, "! Frequency to tune the above note to")?;
+        writeln!(out, "{:.6}", frequency)?;
+        writeln!(out, "! Scale degree to consider as formal octave (determines difference in pitch")?;
+        writeln!(out, "! between adjacent mapping patterns):")?;
+        writeln!(out, "{}", self.octave)?;
+        writeln!(out, "! Mapping.")?;
+        for step in 0..self.octave {
+            writeln!(out, "{}", step)?;
+        }
+        Ok(())
+    }
+
+    /// Write out a Scala `.scl` file for this tuning.
+    pub fn write_scl<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.scl())?;
+        Ok(())
+    }
+
+    /// Write out a Scala `.kbm` file for this tuning.
+    pub fn write_kbm<P: AsRef<Path>>(&self, path: P, reference: &Reference) -> Result<()> {
+        std::fs::write(path, self.kbm(reference)?)?;
+        Ok(())
+    }
 }
 
 pub static EDO12: Edo = Edo {
     octave: 12,
-    channel_octaves: None,
-    middle_c: MidiNote { channel: 1, note: 60 },
-    intervals: EDO12_INTERVALS.as_slice(),
-    sharp_names: EDO12_SHARP_NAMES.as_slice(),
-    flat_names: EDO12_FLAT_NAMES.as_slice(),
+    encoding: Encoding::Sequential(Sequential { channel: 1, middle: 60 }),
+    fifth: 7,
+    names: Names::Table {
+        sharp: EDO12_SHARP_NAMES.as_slice(),
+        flat: EDO12_FLAT_NAMES.as_slice(),
+    },
 };
 
-static EDO12_INTERVALS: [isize; 9] = [
-    1, 1, 2, 3, 4, 5, 6, 6, 7,
-];
-
-static EDO12_SHARP_NAMES: [&'static str; 12] = [
+static EDO12_SHARP_NAMES: [&str; 12] = [
     "C",
     "C♯",
     "D",
@@ -142,7 +581,7 @@ static EDO12_SHARP_NAMES: [&'static str; 12] = [
     "B",
 ];
 
-static EDO12_FLAT_NAMES: [&'static str; 12] = [
+static EDO12_FLAT_NAMES: [&str; 12] = [
     "C",
     "D♭",
     "D",
@@ -159,18 +598,15 @@ static EDO12_FLAT_NAMES: [&'static str; 12] = [
 
 pub static EDO17: Edo = Edo {
     octave: 17,
-    channel_octaves: Some(60),
-    middle_c: MidiNote { channel: 4, note: 60 },
-    intervals: EDO17_INTERVALS.as_slice(),
-    sharp_names: EDO17_SHARP_NAMES.as_slice(),
-    flat_names: EDO17_FLAT_NAMES.as_slice(),
+    encoding: Encoding::ChannelOctaves(ChannelOctaves { bias: 60, middle_channel: 4 }),
+    fifth: 10,
+    names: Names::Table {
+        sharp: EDO17_SHARP_NAMES.as_slice(),
+        flat: EDO17_FLAT_NAMES.as_slice(),
+    },
 };
 
-static EDO17_INTERVALS: [isize; 9] = [
-    2, 1, 3, 4, 6, 7, 9, 8, 10,
-];
-
-static EDO17_SHARP_NAMES: [&'static str; 17] = [
+static EDO17_SHARP_NAMES: [&str; 17] = [
     "C",
     "D♭",
     "C♯",
@@ -190,7 +626,7 @@ static EDO17_SHARP_NAMES: [&'static str; 17] = [
     "B",
 ];
 
-static EDO17_FLAT_NAMES: [&'static str; 17] = [
+static EDO17_FLAT_NAMES: [&str; 17] = [
     "C",
     "D♭",
     "C♯",
@@ -212,18 +648,15 @@ static EDO17_FLAT_NAMES: [&'static str; 17] = [
 
 pub static EDO19: Edo = Edo {
     octave: 19,
-    channel_octaves: Some(60),
-    middle_c: MidiNote { channel: 4, note: 60 },
-    intervals: EDO19_INTERVALS.as_slice(),
-    sharp_names: EDO19_SHARP_NAMES.as_slice(),
-    flat_names: EDO19_FLAT_NAMES.as_slice(),
+    encoding: Encoding::ChannelOctaves(ChannelOctaves { bias: 60, middle_channel: 4 }),
+    fifth: 11,
+    names: Names::Table {
+        sharp: EDO19_SHARP_NAMES.as_slice(),
+        flat: EDO19_FLAT_NAMES.as_slice(),
+    },
 };
 
-static EDO19_INTERVALS: [isize; 9] = [
-    1, 2, 3, 5, 6, 8, 9, 10, 11,
-];
-
-static EDO19_SHARP_NAMES: [&'static str; 19] = [
+static EDO19_SHARP_NAMES: [&str; 19] = [
     "C",
     "C♯",
     "D♭",
@@ -245,7 +678,7 @@ static EDO19_SHARP_NAMES: [&'static str; 19] = [
     "B♯",
 ];
 
-static EDO19_FLAT_NAMES: [&'static str; 19] = [
+static EDO19_FLAT_NAMES: [&str; 19] = [
     "C",
     "C♯",
     "D♭",
@@ -269,18 +702,15 @@ static EDO19_FLAT_NAMES: [&'static str; 19] = [
 
 pub static EDO31: Edo = Edo {
     octave: 31,
-    channel_octaves: Some(60),
-    middle_c: MidiNote { channel: 4, note: 60 },
-    intervals: EDO31_INTERVALS.as_slice(),
-    sharp_names: EDO31_NAMES.as_slice(),
-    flat_names: EDO31_NAMES.as_slice(),
+    encoding: Encoding::ChannelOctaves(ChannelOctaves { bias: 60, middle_channel: 4 }),
+    fifth: 18,
+    names: Names::Table {
+        sharp: EDO31_NAMES.as_slice(),
+        flat: EDO31_NAMES.as_slice(),
+    },
 };
 
-static EDO31_INTERVALS: [isize; 9] = [
-    2, 3, 5, 8, 10, 13, 15, 16, 18,
-];
-
-static EDO31_NAMES: [&'static str; 31] = [
+static EDO31_NAMES: [&str; 31] = [
     "C",
     "D𝄫",
     "C♯",
@@ -316,92 +746,22 @@ static EDO31_NAMES: [&'static str; 31] = [
 
 pub static EDO41: Edo = Edo {
     octave: 41,
-    channel_octaves: Some(60),
-    middle_c: MidiNote { channel: 4, note: 60 },
-    intervals: EDO41_INTERVALS.as_slice(),
-    sharp_names: EDO41_NAMES.as_slice(),
-    flat_names: EDO41_NAMES.as_slice(),
+    encoding: Encoding::ChannelOctaves(ChannelOctaves { bias: 60, middle_channel: 4 }),
+    fifth: 24,
+    names: Names::UpsDowns,
 };
 
-static EDO41_INTERVALS: [isize; 9] = [
-    4, 3, 7, 10, 14, 17, 21, 20, 24,
-];
-
-static EDO41_NAMES: [&'static str; 41] = [
-    "C",
-    "^C",
-    "^^C",
-    "D♭",
-    "C♯",
-    "vvD",
-    "vD",
-    "D",
-    "^D",
-    "^^D",
-    "E♭",
-    "D♯",
-    "vvE",
-    "vE",
-    "E",
-    "E",
-    "vF",
-    "F",
-    "^F",
-    "^^F",
-    "G♭",
-    "F♯",
-    "vvG",
-    "vG",
-    "G",
-    "^G",
-    "^^G",
-    "A♭",
-    "G♯",
-    "vvA",
-    "vA",
-    "A",
-    "^A",
-    "^^A",
-    "B♭",
-    "A♯",
-    "vvB",
-    "vB",
-    "B",
-    "^B",
-    "vC",
-];
-
 pub static EDO53: Edo = Edo {
     octave: 53,
-    channel_octaves: Some(1),
-    middle_c: MidiNote { channel: 4, note: 1 },
-    intervals: EDO53_INTERVALS.as_slice(),
-    sharp_names: EDO53_NAMES.as_slice(),
-    flat_names: EDO53_NAMES.as_slice(),
+    encoding: Encoding::ChannelOctaves(ChannelOctaves { bias: 1, middle_channel: 4 }),
+    fifth: 31,
+    names: Names::Table {
+        sharp: EDO53_NAMES.as_slice(),
+        flat: EDO53_NAMES.as_slice(),
+    },
 };
 
-static EDO53_INTERVALS: [isize; 9] = [
-    // Aug1
-    5,
-    // m2
-    4,
-    // M2
-    9,
-    // m3
-    13,
-    // M3
-    18,
-    // P4
-    22,
-    // Aug4
-    25, // ??
-    // Dim5
-    25,
-    // P5
-    31,
-];
-
-static EDO53_NAMES: [&'static str; 53] = [
+static EDO53_NAMES: [&str; 53] = [
     // 0, C
     "C",
     "^C",
@@ -464,142 +824,166 @@ static EDO53_NAMES: [&'static str; 53] = [
     "vC",
 ];
 
-impl Tuning for Edo {
-    fn get_steps(&self, interval: IntervalStep) -> isize {
-        self.intervals[interval as usize]
+/// Move a note by a number of steps, using the given encoding, where each
+/// period has `size` steps.  The steps can be zero or negative, as some
+/// intervals are in tunings with very flat or very sharp fifths.
+fn move_steps(
+    encoding: &dyn MidiEncoding,
+    size: usize,
+    note: MidiNote,
+    steps: isize,
+    up: bool,
+) -> Option<MidiNote> {
+    let from = encoding.decode(note, size)?;
+    encoding.encode(if up { from + steps } else { from - steps }, size)
+}
+
+/// Color a note of a tuning without note names, by how close it is to the
+/// notes of 12-EDO.  `cents` is the pitch above the start of the period, and
+/// the start of the period, and middle C get their own colors.
+fn cents_color(cents: f64, period_start: bool, middle_c: bool) -> RGB8 {
+    if middle_c {
+        return RGB8::new(150, 150, 192);
+    }
+    if period_start {
+        return RGB8::new(192, 192, 65);
     }
 
-    fn interval(&self, note: MidiNote, interval: Interval) -> Option<MidiNote> {
-        if let Some(bias) = self.channel_octaves {
-            let bias = bias as usize;
-
-            // Bias everything by 100 octaves.  This shouldn't be a problem even
-            // with very fine tunings.
-            let steps = self.get_steps(interval.step);
-            if steps < 0 {
-                // We don't support tunings with negative intervals.
-                todo!();
-            }
-            let steps = steps as usize;
-            let pitch = (100 + note.channel as usize) * self.octave as usize
-                + (note.note as usize - bias);
-            let pitch = if interval.is_up() { pitch + steps } else { pitch - steps };
-            let octave = pitch / self.octave;
-            if octave < 100 || octave > 227 {
-                println!("Out of bound octave: {}", octave);
-                return None;
-            }
-            let octave = octave - 100;
-            let pitch = pitch % self.octave + bias;
-            Some(MidiNote { channel: octave as u8, note: pitch as u8, })
-        } else {
-            let steps = self.get_steps(interval.step);
-            let steps = u8::try_from(steps).ok()?;
-            let pitch = if interval.is_up() { note.note.checked_add(steps)? } else { note.note.checked_sub(steps)? };
-            if pitch > 127 {
-                return None;
+    let semitone = (cents / 100.0).round();
+    if (cents - semitone * 100.0).abs() > 15.0 {
+        return RGB8::new(70, 192, 192);
+    }
+    match (semitone as isize).rem_euclid(12) {
+        1 | 3 | 6 | 8 | 10 => RGB8::new(192, 65, 65),
+        _ => RGB8::new(65, 65, 192),
+    }
+}
+
+/// Pick a color for a note, based on its name (including the octave number).
+/// To start with, just base the color on the length of the note, with a
+/// special case for C4.
+fn name_color(name: &str) -> RGB8 {
+    if name == "C4" {
+        return RGB8::new(150, 150, 192);
+    }
+    // Match names that start with 'C', but aren't accidentals.
+    let mut iter = name.chars();
+    if let Some(ch) = iter.next() {
+        if ch == 'C' {
+            if let Some(ch) = iter.next() {
+                if ch == '-' || ch.is_ascii_digit() {
+                    return RGB8::new(192, 192, 65);
+                }
             }
-            Some(MidiNote { channel: note.channel, note: pitch, })
         }
     }
+    if name.len() == 2 {
+        return RGB8::new(65, 65, 192);
+    }
 
-    fn name(&self, note: MidiNote, sharp: bool) -> String {
-        if let Some(bias) = self.channel_octaves {
-            let pitch = note.note as usize - bias;
-            let octave = note.channel;
-            let names = if sharp { self.sharp_names } else { self.flat_names };
-            format!("{}{}", names[pitch as usize], octave)
-            // format!("{}-{}", octave, pitch)
+    // Pick some additional colors for the up/down variants.
+    let digits: &[_] = &['1', '2', '3', '4', '5', '6', '7', '8', '9', '0'];
+    let stripped = name.trim_end_matches(digits);
+    if name.starts_with("^^") {
+        if stripped.ends_with("♭") {
+            return RGB8::new(192, 65, 192);
         } else {
-            // We assume that Middle C is C-4.
-            let pitch = note.note as isize - self.middle_c.note as isize;
-            let pitch = pitch + self.octave as isize * 4;
-            let octave = pitch / (self.octave as isize);
-            let pitch = pitch % (self.octave as isize);
-            let names = if sharp { self.sharp_names } else { self.flat_names };
-            format!("{}{}", names[pitch as usize], octave)
+            return RGB8::new(192, 169, 70);
         }
     }
-
-    /// To start with, just base the color on the length of the note, with a
-    /// special case for C4.
-    fn color(&self, note: MidiNote, sharp: bool) -> RGB8 {
-        let name = self.name(note, sharp);
-        if name == "C4" {
-            return RGB8::new(150, 150, 192);
-        }
-        // Match names that start with 'C', but aren't accidentals.
-        let mut iter = name.chars();
-        if let Some(ch) = iter.next() {
-            if ch == 'C' {
-                if let Some(ch) = iter.next() {
-                    if ch == '-' || ch.is_digit(10) {
-                        return RGB8::new(192, 192, 65);
-                    }
-                }
-            }
-        }
-        if name.len() == 2 {
-            return RGB8::new(65, 65, 192);
+    if name.starts_with("vv") {
+        if stripped.ends_with("♯") {
+            return RGB8::new(131, 117, 192);
+        } else {
+            return RGB8::new(192, 117, 67);
         }
+    }
+    if name.starts_with("^") {
+        return RGB8::new(65, 192, 65);
+    }
+    if name.starts_with("v") {
+        return RGB8::new(85, 200, 192);
+    }
 
-        // Pick some additional colors for the up/down variants.
-        let digits: &[_] = &['1', '2', '3', '4', '5', '6', '7', '8', '9', '0'];
-        let stripped = name.trim_end_matches(digits);
-        if name.starts_with("^^") {
-            if stripped.ends_with("♭") {
-                return RGB8::new(192, 65, 192);
-            } else {
-                return RGB8::new(192, 169, 70);
-            }
-        }
-        if name.starts_with("vv") {
-            if stripped.ends_with("♯") {
-                return RGB8::new(131, 117, 192);
-            } else {
-                return RGB8::new(192, 117, 67);
-            }
+    // The unusual accidentals are a bit out of place in 31, so give them
+    // their own colors.
+    if name.starts_with("C♭") || name.starts_with("F♭") {
+        // Blend the sharp and double sharp colors.
+        return RGB8::new(131, 117, 192);
+    }
+
+    if name.starts_with("E♯") || name.starts_with("B♯") {
+        // Blend the flat and double flat colors.
+        return RGB8::new(192, 117, 67);
+    }
+
+    // If we are "up" sharps will be the normal color, likewise, flats will
+    // be the normal color down, otherwise use an alternate color.
+    if let Some(pos) = name.char_indices().nth(1) {
+        let name = &name[pos.0..];
+        if name.starts_with("♯") {
+            return RGB8::new(192, 65, 65);
         }
-        if name.starts_with("^") {
-            return RGB8::new(65, 192, 65);
+        if name.starts_with("♭") {
+            return RGB8::new(192, 65, 192);
         }
-        if name.starts_with("v") {
-            return RGB8::new(85, 200, 192);
+        if name.starts_with("𝄪") {
+            return RGB8::new(192, 169, 70);
         }
+        return RGB8::new(70, 192, 192);
+    }
 
-        // The unusual accidentals are a bit out of place in 31, so give them
-        // their own colors.
-        if name.starts_with("C♭") || name.starts_with("F♭") {
-            // Blend the sharp and double sharp colors.
-            return RGB8::new(131, 117, 192);
-        }
+    RGB8::new(130, 192, 130)
+}
 
-        if name.starts_with("E♯") || name.starts_with("B♯") {
-            // Blend the flat and double flat colors.
-            return RGB8::new(192, 117, 67);
-        }
+impl Tuning for Edo {
+    fn get_steps(&self, interval: IntervalStep) -> isize {
+        let (fifths, octaves) = interval.vector();
+        fifths * self.fifth as isize + octaves * self.octave as isize
+    }
 
-        // If we are "up" sharps will be the normal color, likewise, flats will
-        // be the normal color down, otherwise use an alternate color.
-        if let Some(pos) = name.char_indices().skip(1).next() {
-            let name = &name[pos.0..];
-            if name.starts_with("♯") {
-                return RGB8::new(192, 65, 65);
-            }
-            if name.starts_with("♭") {
-                return RGB8::new(192, 65, 192);
-            }
-            if name.starts_with("𝄪") {
-                return RGB8::new(192, 169, 70);
-            }
-            return RGB8::new(70, 192, 192);
+    fn interval_steps(&self, size: IntervalSize) -> Option<isize> {
+        if let IntervalSize::Steps(steps) = size {
+            return Some(steps);
         }
+        let (fifths, octaves) = size.vector()?;
+        Some(fifths * self.fifth as isize + octaves * self.octave as isize)
+    }
+
+    fn interval(&self, note: MidiNote, interval: Interval) -> Option<MidiNote> {
+        let steps = self.interval_steps(interval.size)?;
+        move_steps(&self.encoding, self.octave, note, steps, interval.is_up())
+    }
+
+    fn name(&self, note: MidiNote, sharp: bool) -> String {
+        let Some(steps) = self.encoding.decode(note, self.octave) else {
+            return String::new();
+        };
+        // Middle C is C4.
+        let octave = 4 + steps.div_euclid(self.octave as isize);
+        let pitch = steps.rem_euclid(self.octave as isize);
+        format!("{}{}", self.pitch_name(pitch as usize, sharp), octave)
+    }
 
-        RGB8::new(130, 192, 130)
+    fn color(&self, note: MidiNote, sharp: bool) -> RGB8 {
+        name_color(&self.name(note, sharp))
     }
 
     fn middle_c(&self) -> MidiNote {
-        self.middle_c
+        self.encoding.encode(0, self.octave).expect("Encoding checked for middle C")
+    }
+
+    fn cents(&self, note: MidiNote) -> Option<f64> {
+        let steps = self.encoding.decode(note, self.octave)?;
+        Some(steps as f64 * 1200.0 / self.octave as f64)
+    }
+
+    fn steps(&self, note: MidiNote) -> Option<isize> {
+        self.encoding.decode(note, self.octave)
+    }
+
+    fn period_steps(&self) -> Option<usize> {
+        Some(self.octave)
     }
 }
 
@@ -613,3 +997,140 @@ fn test_edo12() {
     assert_eq!(EDO12.name(MidiNote { channel: 1, note: 61 }, false), "D♭4");
     assert_eq!(EDO12.name(MidiNote { channel: 1, note: 48 }, true), "C3");
 }
+
+#[test]
+fn test_edo_new() {
+    // The generic constructor should agree with the statics.
+    for edo in [&EDO12, &EDO17, &EDO19, &EDO31, &EDO41, &EDO53] {
+        let generic = Edo::new(edo.octave).unwrap();
+        assert_eq!(generic.fifth, edo.fifth);
+    }
+
+    // Check against the interval tables that the statics used to have.
+    let steps = |edo: &Edo| {
+        [
+            IntervalStep::AugUnison, IntervalStep::MinorSecond, IntervalStep::MajorSecond,
+            IntervalStep::MinorThird, IntervalStep::MajorThird, IntervalStep::PerfectFourth,
+            IntervalStep::AugmentedFourth, IntervalStep::DimishedFifth, IntervalStep::PerfectFifth,
+        ].map(|step| edo.get_steps(step))
+    };
+    assert_eq!(steps(&EDO12), [1, 1, 2, 3, 4, 5, 6, 6, 7]);
+    assert_eq!(steps(&EDO17), [2, 1, 3, 4, 6, 7, 9, 8, 10]);
+    assert_eq!(steps(&EDO19), [1, 2, 3, 5, 6, 8, 9, 10, 11]);
+    assert_eq!(steps(&EDO31), [2, 3, 5, 8, 10, 13, 15, 16, 18]);
+    assert_eq!(steps(&EDO41), [4, 3, 7, 10, 14, 17, 21, 20, 24]);
+    assert_eq!(steps(&EDO53), [5, 4, 9, 13, 18, 22, 27, 26, 31]);
+    assert_eq!(steps(&Edo::new(22).unwrap()), [3, 1, 4, 5, 8, 9, 12, 10, 13]);
+
+    let upper = [
+        IntervalStep::MinorSixth, IntervalStep::MajorSixth, IntervalStep::MinorSeventh,
+        IntervalStep::MajorSeventh, IntervalStep::Octave,
+    ];
+    assert_eq!(upper.map(|step| EDO12.get_steps(step)), [8, 9, 10, 11, 12]);
+    assert_eq!(upper.map(|step| EDO31.get_steps(step)), [21, 23, 26, 28, 31]);
+    assert_eq!(EDO31.interval_steps(IntervalSize::Vector { fifths: -1, octaves: 0 }), Some(-18));
+    assert_eq!(EDO31.interval_steps(IntervalSize::Steps(3)), Some(3));
+
+    assert_eq!(Edo::from_val("17").unwrap().fifth(), 10);
+    assert_eq!(Edo::from_val("22b").unwrap().fifth(), 12);
+    assert_eq!(Edo::from_val("72").unwrap().fifth(), 42);
+    assert!(Edo::from_val("17a").is_err());
+    assert!(Edo::from_val("c17").is_err());
+    assert!(Edo::from_val("17c").is_err());
+    assert!(Edo::from_val("22bd").is_err());
+
+    // Sizes without room for a fifth, or a midi channel, are errors.
+    assert!(Edo::new(0).is_err());
+    assert!(Edo::new(1).is_err());
+    assert!(Edo::new(200).is_err());
+    assert!(Edo::with_fifth(12, 0).is_err());
+    assert!(Edo::with_fifth(12, 12).is_err());
+
+    let edo72 = Edo::new(72).unwrap();
+    assert_eq!(edo72.middle_c(), MidiNote { channel: 4, note: 0 });
+    assert_eq!(edo72.name(MidiNote { channel: 4, note: 42 }, true), "G4");
+    assert_eq!(edo72.name(MidiNote { channel: 4, note: 43 }, true), "^G4");
+}
+
+#[test]
+fn test_negative_steps() {
+    use crate::lumatone::{filled, KeyIndex, Region, BOSANQUET};
+
+    // In 22b, the fifth is flat enough that the sharp goes down.
+    let edo = Edo::from_val("22b").unwrap();
+    assert_eq!(edo.get_steps(IntervalStep::AugUnison), -4);
+    let up = Interval::new(IntervalStep::AugUnison, IntervalDirection::Up);
+    let note = edo.interval(edo.middle_c(), up).unwrap();
+    assert_eq!(note, MidiNote { channel: 3, note: 78 });
+    assert_eq!(edo.interval(note, up.flip()), Some(edo.middle_c()));
+
+    // Going up, the flats are the names that raise the pitch.
+    assert_eq!(edo.name(note, true), "A♭3");
+    assert_eq!(edo.name(note, false), "C♯3");
+
+    // And it can be used for a whole keyboard.
+    filled(&edo, &BOSANQUET, KeyIndex { group: 2, key: 27 },
+           Region::Columns { left: 20, right: 20 });
+
+    // 7-EDO has no difference between sharps and naturals.
+    let edo = Edo::with_fifth(7, 4).unwrap();
+    assert_eq!(edo.interval(edo.middle_c(), up), Some(edo.middle_c()));
+    let down = Interval::steps(-3, IntervalDirection::Down);
+    assert_eq!(edo.interval(edo.middle_c(), down), Some(MidiNote { channel: 4, note: 63 }));
+
+    // Single channel tunings move across the whole channel.
+    let down = Interval::steps(-61, IntervalDirection::Up);
+    assert_eq!(EDO12.interval(EDO12.middle_c(), down), None);
+}
+
+#[test]
+fn test_edo_encoding() {
+    // The same tuning, with the notes running across channels.
+    let edo = EDO31.clone().with_encoding(Blocks { channel: 1, middle: 128 + 60 }).unwrap();
+    let c4 = edo.middle_c();
+    assert_eq!(c4, MidiNote { channel: 2, note: 60 });
+    assert_eq!(edo.name(c4, true), "C4");
+    let up = Interval::new(IntervalStep::PerfectFifth, IntervalDirection::Up);
+    let octave = Interval::new(IntervalStep::Octave, IntervalDirection::Up);
+    assert_eq!(edo.interval(c4, up), Some(MidiNote { channel: 2, note: 78 }));
+    let c6 = edo.interval(edo.interval(c4, octave).unwrap(), octave).unwrap();
+    assert_eq!(c6, MidiNote { channel: 2, note: 122 });
+    let c7 = edo.interval(c6, octave).unwrap();
+    assert_eq!(c7, MidiNote { channel: 3, note: 25 });
+    assert_eq!(edo.name(c7, true), "C7");
+    assert_eq!(edo.cents(c7), Some(3600.0));
+
+    // Or on a single channel.
+    let edo = Edo::new(19).unwrap().with_encoding(Sequential { channel: 2, middle: 30 }).unwrap();
+    assert_eq!(edo.name(MidiNote { channel: 2, note: 11 }, true), "C3");
+    assert_eq!(edo.name(MidiNote { channel: 1, note: 30 }, true), "");
+
+    // But not with an encoding that can't hold it.
+    assert!(Edo::new(72).unwrap().with_encoding(ChannelOctaves { bias: 60, middle_channel: 4 }).is_err());
+    assert!(EDO12.clone().with_encoding(Sequential { channel: 0, middle: 60 }).is_err());
+}
+
+#[test]
+fn test_frequency() {
+    let concert = Reference::concert(&EDO12);
+    assert_eq!(concert.note, MidiNote { channel: 1, note: 69 });
+    let c4 = EDO12.frequency(EDO12.middle_c(), &concert).unwrap();
+    assert!((c4 - MIDDLE_C_HZ).abs() < 1e-9);
+
+    let concert = Reference::concert(&EDO31);
+    assert_eq!(concert.note, MidiNote { channel: 4, note: 83 });
+    let g3 = MidiNote { channel: 3, note: 78 };
+    let cents = EDO31.cents(g3).unwrap();
+    assert!((cents - (18.0 - 31.0) * 1200.0 / 31.0).abs() < 1e-9);
+    let cents = EDO31.cents_from(g3, &concert).unwrap();
+    assert!((cents - (18.0 - 31.0 - 23.0) * 1200.0 / 31.0).abs() < 1e-9);
+
+    let reference = Reference::middle_c(&EDO31, 261.63);
+    let c5 = EDO31.frequency(MidiNote { channel: 5, note: 60 }, &reference).unwrap();
+    assert!((c5 - 523.26).abs() < 1e-9);
+
+    // Notes outside of the octave aren't part of the tuning.
+    assert_eq!(EDO31.cents(MidiNote { channel: 4, note: 91 }), None);
+    assert_eq!(EDO31.cents(MidiNote { channel: 4, note: 59 }), None);
+    assert!(Reference::named(&EDO31, "H4", 440.0).is_none());
+}
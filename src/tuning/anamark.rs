@@ -0,0 +1,163 @@
+//! AnaMark tuning files.
+//!
+//! Software instruments that don't read Scala files often read the
+//! [AnaMark](https://www.mark-henning.de/files/am/Tuning_File_V2_Doc.pdf)
+//! `.tun` format, which gives the pitch of each of the 128 midi notes.  A
+//! `.tun` file can only describe a single channel, so for tunings that spread
+//! their notes across channels, a multiple scale `.msf` file holds one scale
+//! for each channel, in channel order.
+
+use std::{fmt::{self, Write}, path::Path};
+
+use anyhow::Result;
+
+use super::{MidiNote, Reference, Tuning};
+
+/// The frequency of midi note 0 in 12-EDO, which all of the pitches in the
+/// file are given relative to.
+const BASE_FREQ: f64 = 8.175_798_915_643_707;
+
+/// Generate the text of a `.tun` file for a single midi channel of the tuning,
+/// tuned to the given reference.  Notes that aren't part of the tuning are
+/// left out, which leaves them at their 12-EDO pitch.
+pub fn tun(tuning: &dyn Tuning, reference: &Reference, channel: u8, name: &str) -> String {
+    let mut out = String::new();
+    write_tun_text(&mut out, tuning, reference, channel, name).expect("Writing to a String can't fail");
+    out
+}
+
+fn write_tun_text(
+    out: &mut String,
+    tuning: &dyn Tuning,
+    reference: &Reference,
+    channel: u8,
+    name: &str,
+) -> fmt::Result {
+    writeln!(out, "; {}", name)?;
+    writeln!(out, ";")?;
+    writeln!(out, "; AnaMark tuning for midi channel {}.", channel)?;
+    writeln!(out)?;
+    write_scale_text(out, tuning, reference, channel, name)
+}
+
+/// Generate the text of a `.msf` multiple scale file, with a scale for each of
+/// the midi channels 1 through 16.
+pub fn msf(tuning: &dyn Tuning, reference: &Reference, name: &str) -> String {
+    let mut out = String::new();
+    write_msf_text(&mut out, tuning, reference, name).expect("Writing to a String can't fail");
+    out
+}
+
+fn write_msf_text(out: &mut String, tuning: &dyn Tuning, reference: &Reference, name: &str) -> fmt::Result {
+    writeln!(out, "; {}", name)?;
+    writeln!(out, ";")?;
+    writeln!(out, "; AnaMark multiple scale file, with one scale for each midi channel.")?;
+    for channel in 1..=16 {
+        writeln!(out)?;
+        write_scale_text(out, tuning, reference, channel, &format!("{} channel {}", name, channel))?;
+    }
+    Ok(())
+}
+
+/// Write out a `.tun` file for a single channel of this tuning.
+pub fn write_tun<P: AsRef<Path>>(
+    path: P,
+    tuning: &dyn Tuning,
+    reference: &Reference,
+    channel: u8,
+    name: &str,
+) -> Result<()> {
+    std::fs::write(path, tun(tuning, reference, channel, name))?;
+    Ok(())
+}
+
+/// Write out a `.msf` file covering all of the channels of this tuning.
+pub fn write_msf<P: AsRef<Path>>(path: P, tuning: &dyn Tuning, reference: &Reference, name: &str) -> Result<()> {
+    std::fs::write(path, msf(tuning, reference, name))?;
+    Ok(())
+}
+
+/// Write out a single scale, from `[Scale Begin]` to `[Scale End]`.  The
+/// older `[Tuning]` section, in whole cents, is given along with the `[Exact
+/// Tuning]` section, for programs that only read the first version of the
+/// format.
+fn write_scale_text(
+    out: &mut String,
+    tuning: &dyn Tuning,
+    reference: &Reference,
+    channel: u8,
+    name: &str,
+) -> fmt::Result {
+    let cents: Vec<(u8, f64)> = (0..128)
+        .filter_map(|note| {
+            let frequency = tuning.frequency(MidiNote { channel, note }, reference)?;
+            Some((note, 1200.0 * (frequency / BASE_FREQ).log2()))
+        })
+        .collect();
+
+    writeln!(out, "[Scale Begin]")?;
+    writeln!(out, "Format= \"AnaMark-TUN\"")?;
+    writeln!(out, "FormatVersion= 200")?;
+    writeln!(out, "FormatSpecs= \"http://www.mark-henning.de/eternity/tuningspecs.html\"")?;
+    writeln!(out)?;
+    writeln!(out, "[Info]")?;
+    writeln!(out, "Name= \"{}\"", name.replace('"', "'"))?;
+    writeln!(out)?;
+    writeln!(out, "[Tuning]")?;
+    for &(note, cents) in &cents {
+        writeln!(out, "note {}= {}", note, cents.round() as isize)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "[Exact Tuning]")?;
+    writeln!(out, "BaseFreq= {:.10}", BASE_FREQ)?;
+    for &(note, cents) in &cents {
+        writeln!(out, "note {}= {:.6}", note, cents)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "[Scale End]")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{msf, tun};
+    use crate::tuning::{Reference, EDO12, EDO31};
+
+    /// Return the values in a section of a single scale.
+    fn section<'a>(text: &'a str, name: &str) -> Vec<&'a str> {
+        text.lines()
+            .skip_while(|line| *line != name)
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn anamark_tun() {
+        let text = tun(&EDO12, &Reference::concert(&EDO12), 1, "12-edo");
+        let exact = section(&text, "[Exact Tuning]");
+        assert_eq!(exact.len(), 129);
+        assert_eq!(exact[0], "BaseFreq= 8.1757989156");
+        assert_eq!(exact[1], "note 0= 0.000000");
+        assert_eq!(exact[70], "note 69= 6900.000000");
+        assert_eq!(section(&text, "[Tuning]")[60], "note 60= 6000");
+        assert!(text.trim_end().ends_with("[Scale End]"));
+
+        // Other channels aren't part of 12-EDO.
+        let text = tun(&EDO12, &Reference::concert(&EDO12), 2, "12-edo");
+        assert!(section(&text, "[Tuning]").is_empty());
+    }
+
+    #[test]
+    fn anamark_msf() {
+        let text = msf(&EDO31, &Reference::concert(&EDO31), "31-edo");
+        assert_eq!(text.matches("[Scale Begin]").count(), 16);
+        assert_eq!(text.matches("[Scale End]").count(), 16);
+
+        // Channel 4 holds the octave starting at middle C, notes 60 to 90.
+        let channel = text.split("[Scale Begin]").nth(4).unwrap();
+        assert!(channel.contains("Name= \"31-edo channel 4\""));
+        let exact = section(channel, "[Exact Tuning]");
+        assert_eq!(exact.len(), 32);
+        assert_eq!(exact[24], "note 83= 6900.000000");
+    }
+}
@@ -0,0 +1,233 @@
+//! Midi encodings.
+//!
+//! The tunings work in steps from middle C.  An encoding decides how those
+//! steps are sent as midi notes, which depends more on the synth being played
+//! than on the tuning itself.  The same Edo can be sent with each octave on
+//! its own channel, or as a single run of notes spread across channels.
+
+use anyhow::{bail, Result};
+
+use super::MidiNote;
+
+/// The lowest and highest midi channels, as numbered by the Lumatone.
+const FIRST_CHANNEL: u8 = 1;
+const LAST_CHANNEL: u8 = 16;
+
+/// Is this a channel the Lumatone can send on.
+fn valid_channel(channel: u8) -> bool {
+    (FIRST_CHANNEL..=LAST_CHANNEL).contains(&channel)
+}
+
+/// A way of converting between steps of a tuning and midi notes.
+pub trait MidiEncoding {
+    /// Return the note for the given number of steps from middle C, in a
+    /// tuning with `size` steps in each period.  None indicates the note can't
+    /// be represented.
+    fn encode(&self, steps: isize, size: usize) -> Option<MidiNote>;
+
+    /// Return the number of steps from middle C of this note.  None indicates
+    /// that the note isn't used by this encoding.
+    fn decode(&self, note: MidiNote, size: usize) -> Option<isize>;
+}
+
+/// All of the notes on a single channel, with middle C at a given note.  This
+/// is the usual encoding for 12-EDO.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Sequential {
+    /// The channel used for every note.
+    pub channel: u8,
+    /// The note number of middle C.
+    pub middle: u8,
+}
+
+impl MidiEncoding for Sequential {
+    fn encode(&self, steps: isize, _size: usize) -> Option<MidiNote> {
+        if !valid_channel(self.channel) {
+            return None;
+        }
+        let note = u8::try_from(self.middle as isize + steps).ok().filter(|&n| n <= 127)?;
+        Some(MidiNote { channel: self.channel, note })
+    }
+
+    fn decode(&self, note: MidiNote, _size: usize) -> Option<isize> {
+        if note.channel != self.channel || note.note > 127 {
+            return None;
+        }
+        Some(note.note as isize - self.middle as isize)
+    }
+}
+
+/// Each octave (or period) is on its own channel, starting at the note
+/// `bias`.  The channel number counts the octaves, with middle C at the start
+/// of `middle_channel`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ChannelOctaves {
+    /// The note number of the start of each octave.
+    pub bias: u8,
+    /// The channel holding the octave that starts at middle C.
+    pub middle_channel: u8,
+}
+
+impl ChannelOctaves {
+    /// Place each octave on its own channel, with middle C on channel 4.  The
+    /// octave starts at note 60 if it will fit, otherwise at note 0.
+    pub const fn new(size: usize) -> ChannelOctaves {
+        let bias = if size + 60 <= 128 { 60 } else { 0 };
+        ChannelOctaves { bias, middle_channel: 4 }
+    }
+}
+
+impl MidiEncoding for ChannelOctaves {
+    fn encode(&self, steps: isize, size: usize) -> Option<MidiNote> {
+        if self.bias as usize + size > 128 {
+            return None;
+        }
+        let size = size as isize;
+        let channel = self.middle_channel as isize + steps.div_euclid(size);
+        let channel = u8::try_from(channel).ok().filter(|&c| valid_channel(c))?;
+        let note = (steps.rem_euclid(size) + self.bias as isize) as u8;
+        Some(MidiNote { channel, note })
+    }
+
+    fn decode(&self, note: MidiNote, size: usize) -> Option<isize> {
+        let pitch = note.note.checked_sub(self.bias)? as usize;
+        if pitch >= size {
+            return None;
+        }
+        let octave = note.channel as isize - self.middle_channel as isize;
+        Some(octave * size as isize + pitch as isize)
+    }
+}
+
+/// The notes run sequentially across channels, in blocks of 128 notes.  Note
+/// 127 of one channel is followed by note 0 of the next.  This suits synths
+/// that treat the channel as the upper bits of a larger note number.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Blocks {
+    /// The channel holding the first block.
+    pub channel: u8,
+    /// The position of middle C, counting from note 0 of the first channel.
+    pub middle: usize,
+}
+
+impl MidiEncoding for Blocks {
+    fn encode(&self, steps: isize, _size: usize) -> Option<MidiNote> {
+        let index = usize::try_from(self.middle as isize + steps).ok()?;
+        let channel = u8::try_from(self.channel as usize + index / 128).ok()
+            .filter(|&c| valid_channel(c))?;
+        Some(MidiNote { channel, note: (index % 128) as u8 })
+    }
+
+    fn decode(&self, note: MidiNote, _size: usize) -> Option<isize> {
+        if note.channel < self.channel || note.channel > LAST_CHANNEL || note.note > 127 {
+            return None;
+        }
+        let index = (note.channel - self.channel) as isize * 128 + note.note as isize;
+        Some(index - self.middle as isize)
+    }
+}
+
+/// One of the encodings above, so that tunings can hold any of them, and
+/// still be used in statics.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Encoding {
+    Sequential(Sequential),
+    ChannelOctaves(ChannelOctaves),
+    Blocks(Blocks),
+}
+
+impl Encoding {
+    /// Check that this encoding can be used for a tuning with `size` steps in
+    /// each period.  At the least, middle C has to be representable.
+    pub fn check(self, size: usize) -> Result<Encoding> {
+        if self.encode(0, size).is_none() {
+            bail!("Middle C of a tuning of {} steps can't be encoded with {:?}", size, self);
+        }
+        Ok(self)
+    }
+}
+
+impl MidiEncoding for Encoding {
+    fn encode(&self, steps: isize, size: usize) -> Option<MidiNote> {
+        match self {
+            Encoding::Sequential(enc) => enc.encode(steps, size),
+            Encoding::ChannelOctaves(enc) => enc.encode(steps, size),
+            Encoding::Blocks(enc) => enc.encode(steps, size),
+        }
+    }
+
+    fn decode(&self, note: MidiNote, size: usize) -> Option<isize> {
+        match self {
+            Encoding::Sequential(enc) => enc.decode(note, size),
+            Encoding::ChannelOctaves(enc) => enc.decode(note, size),
+            Encoding::Blocks(enc) => enc.decode(note, size),
+        }
+    }
+}
+
+impl From<Sequential> for Encoding {
+    fn from(enc: Sequential) -> Encoding {
+        Encoding::Sequential(enc)
+    }
+}
+
+impl From<ChannelOctaves> for Encoding {
+    fn from(enc: ChannelOctaves) -> Encoding {
+        Encoding::ChannelOctaves(enc)
+    }
+}
+
+impl From<Blocks> for Encoding {
+    fn from(enc: Blocks) -> Encoding {
+        Encoding::Blocks(enc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Blocks, ChannelOctaves, Encoding, MidiEncoding, Sequential};
+    use crate::tuning::MidiNote;
+
+    /// Every note that encodes should decode back to the same steps.
+    fn round_trip(enc: &dyn MidiEncoding, size: usize) {
+        for steps in -1000..1000 {
+            if let Some(note) = enc.encode(steps, size) {
+                assert_eq!(enc.decode(note, size), Some(steps));
+            }
+        }
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        let seq = Sequential { channel: 1, middle: 60 };
+        round_trip(&seq, 12);
+        assert_eq!(seq.encode(-60, 12), Some(MidiNote { channel: 1, note: 0 }));
+        assert_eq!(seq.encode(-61, 12), None);
+        assert_eq!(seq.decode(MidiNote { channel: 2, note: 60 }, 12), None);
+
+        let oct = ChannelOctaves::new(31);
+        round_trip(&oct, 31);
+        assert_eq!(oct.encode(-13, 31), Some(MidiNote { channel: 3, note: 78 }));
+        assert_eq!(oct.decode(MidiNote { channel: 4, note: 91 }, 31), None);
+        assert_eq!(ChannelOctaves::new(72).bias, 0);
+
+        let blocks = Blocks { channel: 1, middle: 128 * 2 + 60 };
+        round_trip(&blocks, 72);
+        assert_eq!(blocks.encode(68, 72), Some(MidiNote { channel: 4, note: 0 }));
+        assert_eq!(blocks.encode(-128 * 2 - 61, 72), None);
+
+        // Channel 0 isn't a channel.
+        let seq = Sequential { channel: 0, middle: 60 };
+        assert_eq!(seq.encode(0, 12), None);
+        let oct = ChannelOctaves { bias: 60, middle_channel: 1 };
+        assert_eq!(oct.encode(-1, 12), None);
+        let blocks = Blocks { channel: 0, middle: 60 };
+        assert_eq!(blocks.encode(0, 12), None);
+        assert_eq!(blocks.encode(128, 12), Some(MidiNote { channel: 1, note: 60 }));
+
+        // An encoding that can't hold middle C isn't usable.
+        let big = ChannelOctaves { bias: 60, middle_channel: 4 };
+        assert!(Encoding::from(big).check(72).is_err());
+        assert!(Encoding::from(big).check(31).is_ok());
+    }
+}
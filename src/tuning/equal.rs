@@ -0,0 +1,227 @@
+//! Equal step tunings that don't repeat at the octave.
+//!
+//! These are tunings made of equal sized steps, where the period is some
+//! other interval, such as the 3/1 tritave of Bohlen-Pierce, a stretched
+//! octave, or no period at all (such as 88-cET).
+
+use anyhow::{bail, Result};
+
+use crate::lumatone::RGB8;
+
+use super::{
+    cents_color, move_steps, ratio_cents, ChannelOctaves, Encoding, Interval, IntervalSize, IntervalStep,
+    MidiEncoding, MidiNote, Sequential, Tuning,
+};
+
+/// A tuning of equal steps, with an arbitrary period.
+#[derive(Debug, Clone)]
+pub struct EqualSteps {
+    /// The size of a single step, in cents.
+    step: f64,
+    /// The number of steps in a period, if this tuning has a period.
+    period: Option<usize>,
+    /// How the steps are sent as midi notes.  Middle C is the start of the
+    /// middle period, or the middle note if there is no period.
+    encoding: Encoding,
+}
+
+impl EqualSteps {
+    /// Divide the given frequency ratio into `steps` equal steps, which will
+    /// be the period.  Each period is placed on its own midi channel, the same
+    /// way as the Edo tunings.  Fails if the period won't fit in a channel, or
+    /// the ratio isn't larger than the unison.
+    pub fn divide(steps: usize, ratio: f64) -> Result<EqualSteps> {
+        if !ratio.is_finite() || ratio <= 1.0 {
+            bail!("Ratio of {} must be larger than the unison", ratio);
+        }
+        EqualSteps::divide_cents(steps, ratio_cents(ratio))
+    }
+
+    /// Divide a period of the given number of cents into `steps` equal steps.
+    /// This is useful for stretched octaves.  Fails if the period won't fit
+    /// in a channel, or isn't larger than the unison.
+    pub fn divide_cents(steps: usize, period: f64) -> Result<EqualSteps> {
+        if steps == 0 || steps > 128 {
+            bail!("Period of {} steps doesn't fit in a midi channel", steps);
+        }
+        if !period.is_finite() || period <= 0.0 {
+            bail!("Period of {} cents must be larger than the unison", period);
+        }
+        Ok(EqualSteps {
+            step: period / steps as f64,
+            period: Some(steps),
+            encoding: Encoding::ChannelOctaves(ChannelOctaves::new(steps)),
+        })
+    }
+
+    /// The Bohlen-Pierce scale, 13 equal divisions of the tritave (3/1).
+    pub fn bohlen_pierce() -> EqualSteps {
+        EqualSteps::divide(13, 3.0).expect("Bohlen-Pierce is a valid division")
+    }
+
+    /// Equal steps of the given number of cents, that never repeat.  This uses
+    /// a single midi channel, with the middle note at note 60.  Fails if the
+    /// step isn't larger than the unison.
+    pub fn unperiodic(step: f64) -> Result<EqualSteps> {
+        if !step.is_finite() || step <= 0.0 {
+            bail!("Step of {} cents must be larger than the unison", step);
+        }
+        Ok(EqualSteps {
+            step,
+            period: None,
+            encoding: Encoding::Sequential(Sequential { channel: 1, middle: 60 }),
+        })
+    }
+
+    /// Send the notes of this tuning with a different midi encoding.  Fails if
+    /// the encoding can't hold middle C of this tuning.
+    pub fn with_encoding<E: Into<Encoding>>(self, encoding: E) -> Result<EqualSteps> {
+        let encoding = encoding.into().check(self.size())?;
+        Ok(EqualSteps { encoding, ..self })
+    }
+
+    /// The size of a single step, in cents.
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// The number of steps in the period, if there is one.
+    pub fn period(&self) -> Option<usize> {
+        self.period
+    }
+
+    /// Return the steps above the start of the period, and the period number,
+    /// for the given note.  Without a period, this is just the number of steps
+    /// from the middle note.
+    fn degree(&self, note: MidiNote) -> Option<(isize, isize)> {
+        let steps = self.encoding.decode(note, self.size())?;
+        match self.period {
+            Some(period) => {
+                let period = period as isize;
+                Some((steps.rem_euclid(period), steps.div_euclid(period) + 4))
+            }
+            None => Some((steps, 0)),
+        }
+    }
+
+    /// The size used for midi placement.  Without a period, the size only
+    /// matters to encodings that place each period on its own channel.
+    fn size(&self) -> usize {
+        self.period.unwrap_or(1)
+    }
+}
+
+impl Tuning for EqualSteps {
+    /// Use the number of steps that comes closest to the just version of the
+    /// interval.
+    fn get_steps(&self, interval: IntervalStep) -> isize {
+        (interval.just_cents() / self.step).round() as isize
+    }
+
+    fn interval_steps(&self, size: IntervalSize) -> Option<isize> {
+        match size {
+            IntervalSize::Steps(steps) => Some(steps),
+            size => Some((size.just_cents()? / self.step).round() as isize),
+        }
+    }
+
+    fn interval(&self, note: MidiNote, interval: Interval) -> Option<MidiNote> {
+        let steps = self.interval_steps(interval.size())?;
+        move_steps(&self.encoding, self.size(), note, steps, interval.is_up())
+    }
+
+    /// Name the notes by the step within the period, followed by the period
+    /// number, with the middle period being 4.  Without a period, the name is
+    /// just the number of steps from the middle note.
+    fn name(&self, note: MidiNote, _sharp: bool) -> String {
+        match (self.degree(note), self.period) {
+            (Some((degree, period)), Some(_)) => format!("{}_{}", degree, period),
+            (Some((steps, _)), None) => format!("{}", steps),
+            (None, _) => String::new(),
+        }
+    }
+
+    fn color(&self, note: MidiNote, _sharp: bool) -> RGB8 {
+        let (degree, period) = self.degree(note).unwrap_or((0, 0));
+        let period_start = self.period.is_some() && degree == 0;
+        let middle = degree == 0 && (self.period.is_none() || period == 4);
+        cents_color(degree as f64 * self.step, period_start, middle)
+    }
+
+    fn middle_c(&self) -> MidiNote {
+        self.encoding.encode(0, self.size()).expect("Encoding checked for middle C")
+    }
+
+    fn cents(&self, note: MidiNote) -> Option<f64> {
+        let steps = self.encoding.decode(note, self.size())?;
+        Some(steps as f64 * self.step)
+    }
+
+    fn steps(&self, note: MidiNote) -> Option<isize> {
+        self.encoding.decode(note, self.size())
+    }
+
+    fn period_steps(&self) -> Option<usize> {
+        self.period
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EqualSteps;
+    use crate::lumatone::{filled, KeyIndex, Region, WICKI_HAYDEN};
+    use crate::tuning::{Interval, IntervalDirection, IntervalStep, MidiNote, Tuning};
+
+    #[test]
+    fn equal_bohlen_pierce() {
+        let bp = EqualSteps::bohlen_pierce();
+        assert_eq!(bp.period(), Some(13));
+        assert_eq!(bp.get_steps(IntervalStep::PerfectFifth), 5);
+
+        // A full tritave up moves to the next channel.
+        let c4 = bp.middle_c();
+        let c5 = MidiNote { channel: 5, note: 60 };
+        assert!((bp.cents(c5).unwrap() - 1901.955).abs() < 1e-3);
+        assert_eq!(bp.name(c5, true), "0_5");
+
+        let down = Interval::new(IntervalStep::PerfectFifth, IntervalDirection::Down);
+        let note = bp.interval(c4, down).unwrap();
+        assert_eq!(note, MidiNote { channel: 3, note: 68 });
+        assert_eq!(bp.name(note, true), "8_3");
+
+        // It should be usable for a layout.
+        let keyb = filled(&bp, &WICKI_HAYDEN, KeyIndex { group: 2, key: 27 },
+                          Region::Columns { left: 4, right: 4 });
+        assert_eq!(keyb.get(KeyIndex { group: 2, key: 27 }).unwrap().label, "0_4");
+    }
+
+    #[test]
+    fn equal_unperiodic() {
+        let cet = EqualSteps::unperiodic(88.0).unwrap();
+        assert_eq!(cet.period(), None);
+        assert_eq!(cet.get_steps(IntervalStep::PerfectFifth), 8);
+
+        let up = Interval::new(IntervalStep::PerfectFifth, IntervalDirection::Up);
+        let note = cet.interval(cet.middle_c(), up).unwrap();
+        assert_eq!(note, MidiNote { channel: 1, note: 68 });
+        assert_eq!(cet.cents(note), Some(704.0));
+        assert_eq!(cet.name(note, true), "8");
+
+        // Stretched octaves still step across channels.
+        let stretched = EqualSteps::divide_cents(12, 1202.0).unwrap();
+        assert!((stretched.cents(MidiNote { channel: 5, note: 61 }).unwrap() - 1302.1667).abs() < 1e-3);
+    }
+
+    #[test]
+    fn equal_invalid() {
+        assert!(EqualSteps::divide(0, 2.0).is_err());
+        assert!(EqualSteps::divide(129, 2.0).is_err());
+        assert!(EqualSteps::divide(12, 1.0).is_err());
+        assert!(EqualSteps::divide(12, 0.5).is_err());
+        assert!(EqualSteps::divide_cents(12, -1200.0).is_err());
+        assert!(EqualSteps::divide_cents(12, f64::INFINITY).is_err());
+        assert!(EqualSteps::unperiodic(0.0).is_err());
+        assert!(EqualSteps::unperiodic(-88.0).is_err());
+        assert!(EqualSteps::unperiodic(f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! MIDI Tuning Standard system exclusive messages.
+//!
+//! Synths that understand the MIDI Tuning Standard can be retuned to match a
+//! mapping by sending them a `.syx` file.  The bulk dump gives the frequency of
+//! all 128 notes of a single tuning program, and is generated for each channel
+//! the mapping uses.  Tunings that repeat every 12 notes can also be sent as a
+//! scale/octave message, which retunes whole channels at once.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::{MidiNote, Reference, Tuning};
+
+/// The device ID that addresses all devices.
+const ALL_DEVICES: u8 = 0x7f;
+
+/// The frequency data for a note that shouldn't be retuned.
+const NO_CHANGE: [u8; 3] = [0x7f, 0x7f, 0x7f];
+
+/// Generate a bulk tuning dump for a single midi channel of the tuning, tuned
+/// to the given reference.  The dump is stored in tuning program `program`,
+/// with the given name (truncated to 16 characters).  Notes that aren't part
+/// of the tuning are left unchanged.
+pub fn bulk_dump(
+    tuning: &dyn Tuning,
+    reference: &Reference,
+    channel: u8,
+    program: u8,
+    name: &str,
+) -> Vec<u8> {
+    let mut body = vec![0x7e, ALL_DEVICES, 0x08, 0x01, program & 0x7f];
+
+    let mut title = [b' '; 16];
+    for (dest, ch) in title.iter_mut().zip(name.bytes().filter(|ch| (0x20..0x7f).contains(ch))) {
+        *dest = ch;
+    }
+    body.extend_from_slice(&title);
+
+    for note in 0..128 {
+        let frequency = tuning.frequency(MidiNote { channel, note }, reference);
+        body.extend_from_slice(&frequency.map_or(NO_CHANGE, frequency_data));
+    }
+
+    let checksum = body.iter().fold(0, |sum, byte| sum ^ byte) & 0x7f;
+    let mut message = vec![0xf0];
+    message.extend_from_slice(&body);
+    message.push(checksum);
+    message.push(0xf7);
+    message
+}
+
+/// Generate a bulk tuning dump for each of the given channels, one after
+/// another.  Each channel is stored in the tuning program of the same number.
+pub fn bulk_dumps(tuning: &dyn Tuning, reference: &Reference, channels: &[u8], name: &str) -> Vec<u8> {
+    channels
+        .iter()
+        .flat_map(|&channel| bulk_dump(tuning, reference, channel, channel, name))
+        .collect()
+}
+
+/// Generate a scale/octave tuning message (the two byte form), covering the
+/// given channels.  This only works if, on each of those channels, the tuning
+/// repeats every 12 notes, and is within a semitone of 12-EDO.  Returns None
+/// otherwise.
+pub fn scale_octave(tuning: &dyn Tuning, reference: &Reference, channels: &[u8]) -> Option<Vec<u8>> {
+    let mut offsets: Option<[f64; 12]> = None;
+    let mut mask = 0u32;
+    for &channel in channels {
+        // Midi channels are numbered from 1 here, as they are on the Lumatone.
+        if !(1..=16).contains(&channel) {
+            return None;
+        }
+        mask |= 1 << (channel - 1);
+
+        // The offset of each note from 12-EDO, in cents.
+        let cents = (0..128u8)
+            .map(|note| {
+                let frequency = tuning.frequency(MidiNote { channel, note }, reference)?;
+                Some((midi_pitch(frequency) - note as f64) * 100.0)
+            })
+            .collect::<Option<Vec<f64>>>()?;
+        let expect = *offsets.get_or_insert_with(|| std::array::from_fn(|note| cents[note]));
+        if cents.iter().enumerate().any(|(note, cents)| (cents - expect[note % 12]).abs() > 0.01) {
+            return None;
+        }
+    }
+
+    let offsets = offsets?;
+    if offsets.iter().any(|cents| !(-100.0..100.0).contains(cents)) {
+        return None;
+    }
+
+    let mut message = vec![
+        0xf0,
+        0x7e,
+        ALL_DEVICES,
+        0x08,
+        0x09,
+        ((mask >> 14) & 0x03) as u8,
+        ((mask >> 7) & 0x7f) as u8,
+        (mask & 0x7f) as u8,
+    ];
+    for cents in offsets {
+        // 0x2000 is no change, and each step is 100/8192 cents.
+        let value = (8192.0 + cents * 8192.0 / 100.0).round().clamp(0.0, 16383.0) as u16;
+        message.push((value >> 7) as u8);
+        message.push((value & 0x7f) as u8);
+    }
+    message.push(0xf7);
+    Some(message)
+}
+
+/// Write out a `.syx` file with a bulk tuning dump for each of the given
+/// channels.
+pub fn write_syx<P: AsRef<Path>>(
+    path: P,
+    tuning: &dyn Tuning,
+    reference: &Reference,
+    channels: &[u8],
+    name: &str,
+) -> Result<()> {
+    std::fs::write(path, bulk_dumps(tuning, reference, channels, name))?;
+    Ok(())
+}
+
+/// The fractional midi note number of a frequency, with A4 (note 69) at 440
+/// Hz.
+fn midi_pitch(frequency: f64) -> f64 {
+    69.0 + 12.0 * (frequency / 440.0).log2()
+}
+
+/// Encode a frequency in the three byte form used by the bulk dump: a
+/// semitone, and a 14 bit fraction of a semitone above it.
+fn frequency_data(frequency: f64) -> [u8; 3] {
+    let pitch = midi_pitch(frequency);
+    if pitch < 0.0 {
+        return [0, 0, 0];
+    }
+    let mut semitone = pitch.floor();
+    let mut fraction = ((pitch - semitone) * 16384.0).round();
+    if fraction >= 16384.0 {
+        semitone += 1.0;
+        fraction = 0.0;
+    }
+    if semitone >= 127.0 && fraction > 0.0 || semitone > 127.0 {
+        // The highest value is reserved to mean no change.
+        return [0x7f, 0x7f, 0x7e];
+    }
+    let fraction = fraction as u16;
+    [semitone as u8, (fraction >> 7) as u8, (fraction & 0x7f) as u8]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bulk_dump, frequency_data, scale_octave};
+    use crate::tuning::{Reference, Tuning, EDO12, EDO31};
+
+    #[test]
+    fn mts_bulk_dump() {
+        let concert = Reference::concert(&EDO31);
+        let dump = bulk_dump(&EDO31, &concert, 4, 4, "31-edo");
+        assert_eq!(dump.len(), 408);
+        assert_eq!(&dump[..6], [0xf0, 0x7e, 0x7f, 0x08, 0x01, 0x04]);
+        assert_eq!(&dump[6..22], b"31-edo          ");
+        assert_eq!(*dump.last().unwrap(), 0xf7);
+
+        // Notes outside of the octave aren't changed.
+        let data = |note: usize| &dump[22 + 3 * note..25 + 3 * note];
+        assert_eq!(data(59), [0x7f, 0x7f, 0x7f]);
+        assert_eq!(data(91), [0x7f, 0x7f, 0x7f]);
+
+        // A4 is note 69 exactly, and middle C is a bit above note 60.
+        assert_eq!(data(83), [69, 0, 0]);
+        let c4 = EDO31.frequency(EDO31.middle_c(), &concert).unwrap();
+        assert!(c4 > 261.63);
+        assert_eq!(data(60)[0], 60);
+
+        let checksum = dump[1..dump.len() - 2].iter().fold(0, |sum, byte| sum ^ byte);
+        assert_eq!(dump[dump.len() - 2], checksum & 0x7f);
+
+        assert_eq!(frequency_data(440.0 * 2f64.powf(1.0 / 24.0)), [69, 0x40, 0]);
+    }
+
+    #[test]
+    fn mts_scale_octave() {
+        let reference = Reference::named(&EDO12, "A4", 432.0).unwrap();
+        let message = scale_octave(&EDO12, &reference, &[1]).unwrap();
+        assert_eq!(message.len(), 33);
+        assert_eq!(&message[..8], [0xf0, 0x7e, 0x7f, 0x08, 0x09, 0, 0, 1]);
+        // Every note is 31.77 cents flat.
+        let value = (message[8] as u16) << 7 | message[9] as u16;
+        assert_eq!(value, 8192 - 2602);
+
+        // 31-EDO doesn't repeat every 12 notes.
+        assert!(scale_octave(&EDO31, &Reference::concert(&EDO31), &[4]).is_none());
+    }
+}
@@ -0,0 +1,144 @@
+//! Note naming for arbitrary EDOs.
+//!
+//! Names are derived from the chain of fifths, using
+//! [ups and downs](https://en.xen.wiki/w/Ups_and_downs_notation) for the steps
+//! that can't be reached with the natural notes and sharps and flats.
+
+/// The natural notes, and their position along the chain of fifths, relative
+/// to C.
+static NATURALS: [(&str, isize); 7] = [
+    ("F", -1),
+    ("C", 0),
+    ("G", 1),
+    ("D", 2),
+    ("A", 3),
+    ("E", 4),
+    ("B", 5),
+];
+
+/// Accidentals, from double flat to double sharp.
+static ACCIDENTALS: [&str; 5] = ["𝄫", "♭", "", "♯", "𝄪"];
+
+/// The ordering used to pick the best name, smaller is better.
+type NameKey = (isize, isize, isize, isize, isize);
+
+/// Return the ups and downs name of `step` (within the octave) for an EDO of
+/// the given size and fifth.  When there is a choice, `sharp` prefers sharps
+/// and ups over flats and downs.
+///
+/// If every step can be named with at most a double sharp or flat, ups and
+/// downs aren't used at all.  Otherwise, notes are named with single sharps
+/// and flats, along with ups and downs, using the fewest total accidentals,
+/// and then the fewest sharps or flats.
+pub fn ups_and_downs(octave: usize, fifth: usize, step: usize, sharp: bool) -> String {
+    let needs_ups = (0..octave).any(|step| {
+        candidates(octave, fifth, step, 2).all(|(_, _, _, ups)| ups != 0)
+    });
+    let max_sharps = if needs_ups { 1 } else { 2 };
+
+    let bias = if sharp { -1 } else { 1 };
+    let mut best: Option<(NameKey, String)> = None;
+    for (letter, fifths, sharps, ups) in candidates(octave, fifth, step, max_sharps) {
+        if ups != 0 && !needs_ups {
+            continue;
+        }
+        // Avoid the unusual single accidentals (C♭, F♭, E♯, B♯) when there is
+        // another name available.  Double sharps and flats prefer the name
+        // closest to D on the chain of fifths.
+        let position = fifths + 7 * sharps;
+        let unusual = if sharps.abs() > 1 {
+            (position - 2).abs()
+        } else {
+            !(-6..=10).contains(&position) as isize
+        };
+        let key = (ups.abs() + sharps.abs(), sharps.abs(), unusual, bias * sharps, bias * ups);
+        if best.as_ref().is_none_or(|(best, _)| key < *best) {
+            let marks = if ups >= 0 { "^" } else { "v" };
+            let name = format!("{}{}{}",
+                               marks.repeat(ups.unsigned_abs()),
+                               letter,
+                               ACCIDENTALS[(sharps + 2) as usize]);
+            best = Some((key, name));
+        }
+    }
+    best.unwrap().1
+}
+
+/// Generate all of the ways of naming a step, as a natural note, its position
+/// on the chain of fifths, the number of sharps (negative for flats), and the
+/// number of ups (negative for downs).
+fn candidates(
+    octave: usize,
+    fifth: usize,
+    step: usize,
+    max_sharps: isize,
+) -> impl Iterator<Item = (&'static str, isize, isize, isize)> {
+    let octave = octave as isize;
+    let fifth = fifth as isize;
+    let step = step as isize;
+    let apotome = 7 * fifth - 4 * octave;
+
+    NATURALS.iter().flat_map(move |&(letter, fifths)| {
+        let natural = (fifths * fifth).rem_euclid(octave);
+        (-max_sharps..=max_sharps).map(move |sharps| {
+            let base = natural + sharps * apotome;
+
+            // The ups needed to get from this note to the step.  Notes at the
+            // top of the octave can also be named from the C above (such as
+            // C♭), but notes at the bottom are not named from the B below.
+            let ups = [step - base, step - octave - base]
+                .into_iter()
+                .min_by_key(|ups| ups.abs())
+                .unwrap();
+            (letter, fifths, sharps, ups)
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::ups_and_downs;
+    use crate::tuning::{Names, EDO12, EDO17, EDO19, EDO31};
+
+    fn names(octave: usize, fifth: usize, sharp: bool) -> Vec<String> {
+        (0..octave).map(|step| ups_and_downs(octave, fifth, step, sharp)).collect()
+    }
+
+    #[test]
+    fn names_match_tables() {
+        for edo in [&EDO12, &EDO17, &EDO19, &EDO31] {
+            let Names::Table { sharp, flat } = edo.names else {
+                panic!("Expecting a name table");
+            };
+            assert_eq!(names(edo.octave, edo.fifth, true), sharp);
+            assert_eq!(names(edo.octave, edo.fifth, false), flat);
+        }
+    }
+
+    #[test]
+    fn names_traditional() {
+        // The EDOs that don't need ups and downs should come out the same as
+        // the traditional names.
+        assert_eq!(names(12, 7, true),
+                   ["C", "C♯", "D", "D♯", "E", "F", "F♯", "G", "G♯", "A", "A♯", "B"]);
+        assert_eq!(names(12, 7, false),
+                   ["C", "D♭", "D", "E♭", "E", "F", "G♭", "G", "A♭", "A", "B♭", "B"]);
+        assert_eq!(names(19, 11, true),
+                   ["C", "C♯", "D♭", "D", "D♯", "E♭", "E", "E♯", "F", "F♯", "G♭", "G",
+                    "G♯", "A♭", "A", "A♯", "B♭", "B", "B♯"]);
+        assert_eq!(names(19, 11, false)[7], "F♭");
+        assert_eq!(names(31, 18, true)[..6], ["C", "D𝄫", "C♯", "D♭", "C𝄪", "D"]);
+        assert_eq!(names(31, 18, true)[29..], ["C♭", "B♯"]);
+    }
+
+    #[test]
+    fn names_ups_and_downs() {
+        assert_eq!(names(41, 24, true)[..18],
+                   ["C", "^C", "^^C", "D♭", "C♯", "vvD", "vD", "D", "^D", "^^D", "E♭", "D♯",
+                    "vvE", "vE", "E", "^E", "vF", "F"]);
+        assert_eq!(names(41, 24, true)[39..], ["^B", "vC"]);
+        assert_eq!(names(72, 42, true)[..4], ["C", "^C", "^^C", "^^^C"]);
+        assert_eq!(names(72, 42, true)[5], "vC♯");
+        assert_eq!(names(72, 42, false)[5], "vD♭");
+    }
+}
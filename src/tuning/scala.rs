@@ -0,0 +1,280 @@
+//! Scala scale files.
+//!
+//! Support for tunings described by a [Scala](https://www.huygens-fokker.org/scala/scl_format.html)
+//! `.scl` file.  The scale is repeated at its period (the last pitch in the
+//! file), which is usually, but not always, an octave.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::lumatone::RGB8;
+
+use super::{
+    cents_color, move_steps, ratio_cents, ChannelOctaves, Encoding, Interval, IntervalSize, IntervalStep,
+    MidiEncoding, MidiNote, Sequential, Tuning,
+};
+
+/// A tuning read from a Scala `.scl` file.
+#[derive(Debug, Clone)]
+pub struct ScalaTuning {
+    /// The description line from the file.
+    description: String,
+    /// The pitch of each scale degree, in cents above the tonic.  The first
+    /// entry is always the tonic itself, 0.0.
+    degrees: Vec<f64>,
+    /// The size of the period, in cents.
+    period: f64,
+    /// How the steps are sent as midi notes, with the tonic of the middle
+    /// octave as middle C.
+    encoding: Encoding,
+}
+
+impl ScalaTuning {
+    /// Read a scale from a `.scl` file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ScalaTuning> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        ScalaTuning::parse(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Parse the text of a `.scl` file.
+    pub fn parse(text: &str) -> Result<ScalaTuning> {
+        let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+
+        let description = lines
+            .next()
+            .ok_or_else(|| anyhow!("Missing description line"))?
+            .trim()
+            .to_string();
+
+        let count = lines
+            .next()
+            .ok_or_else(|| anyhow!("Missing note count"))?
+            .trim()
+            .parse::<usize>()?;
+        if count == 0 {
+            bail!("Scale has no notes");
+        }
+
+        let mut degrees = vec![0.0];
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            // Anything after the first token is a comment.
+            let token = line.split_whitespace().next().unwrap();
+            degrees.push(parse_pitch(token)?);
+        }
+        if degrees.len() != count + 1 {
+            bail!("Expecting {} notes, found {}", count, degrees.len() - 1);
+        }
+
+        let period = degrees.pop().unwrap();
+        if period <= 0.0 {
+            bail!("Period must be larger than the unison");
+        }
+
+        // Keep to the same conventions as the Edo tunings, putting each period
+        // on its own channel, if it will fit.
+        if count > 128 {
+            bail!("Scale of {} notes doesn't fit in a midi channel", count);
+        }
+
+        Ok(ScalaTuning {
+            description,
+            degrees,
+            period,
+            encoding: Encoding::ChannelOctaves(ChannelOctaves::new(count)),
+        })
+    }
+
+    /// Use a single midi channel for this scale, with the tonic of the middle
+    /// octave at note 60.  Only useful for small scales.
+    pub fn single_channel(self) -> ScalaTuning {
+        ScalaTuning { encoding: Encoding::Sequential(Sequential { channel: 1, middle: 60 }), ..self }
+    }
+
+    /// Send the notes of this scale with a different midi encoding.  Fails if
+    /// the encoding can't hold middle C of this scale.
+    pub fn with_encoding<E: Into<Encoding>>(self, encoding: E) -> Result<ScalaTuning> {
+        let encoding = encoding.into().check(self.size())?;
+        Ok(ScalaTuning { encoding, ..self })
+    }
+
+    /// The description from the scale file.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The number of notes in each period.
+    pub fn size(&self) -> usize {
+        self.degrees.len()
+    }
+
+    /// The size of the period, in cents.
+    pub fn period(&self) -> f64 {
+        self.period
+    }
+
+    /// The pitch, in cents, of a given number of steps above the tonic.
+    fn step_cents(&self, steps: isize) -> f64 {
+        let size = self.size() as isize;
+        let octave = steps.div_euclid(size);
+        let degree = steps.rem_euclid(size) as usize;
+        octave as f64 * self.period + self.degrees[degree]
+    }
+
+    /// The number of steps, up to two periods in either direction, that comes
+    /// closest to the given number of cents.
+    fn closest_steps(&self, target: f64) -> isize {
+        let size = self.size() as isize;
+        let mut best = 0;
+        for steps in -2 * size..=2 * size {
+            if (self.step_cents(steps) - target).abs() < (self.step_cents(best) - target).abs() {
+                best = steps;
+            }
+        }
+        best
+    }
+
+    /// Return the degree and octave of the given note, with the middle octave
+    /// being 4.
+    fn degree(&self, note: MidiNote) -> Option<(usize, isize)> {
+        let size = self.size() as isize;
+        let steps = self.encoding.decode(note, self.size())?;
+        Some((steps.rem_euclid(size) as usize, steps.div_euclid(size) + 4))
+    }
+}
+
+/// Parse a single pitch value.  Values with a period are in cents, otherwise
+/// they are a ratio, or a whole number.
+fn parse_pitch(token: &str) -> Result<f64> {
+    if token.contains('.') {
+        return Ok(token.parse::<f64>()?);
+    }
+
+    let (num, den) = match token.split_once('/') {
+        Some((num, den)) => (num.parse::<u64>()?, den.parse::<u64>()?),
+        None => (token.parse::<u64>()?, 1),
+    };
+    if num == 0 || den == 0 {
+        bail!("Invalid ratio: {:?}", token);
+    }
+    Ok(ratio_cents(num as f64 / den as f64))
+}
+
+impl Tuning for ScalaTuning {
+    /// Use the number of steps that comes closest to the just version of the
+    /// interval.
+    fn get_steps(&self, interval: IntervalStep) -> isize {
+        self.closest_steps(interval.just_cents())
+    }
+
+    fn interval_steps(&self, size: IntervalSize) -> Option<isize> {
+        match size {
+            IntervalSize::Steps(steps) => Some(steps),
+            size => Some(self.closest_steps(size.just_cents()?)),
+        }
+    }
+
+    fn interval(&self, note: MidiNote, interval: Interval) -> Option<MidiNote> {
+        let steps = self.interval_steps(interval.size())?;
+        move_steps(&self.encoding, self.size(), note, steps, interval.is_up())
+    }
+
+    /// Scales don't have note names, so use the degree number, followed by the
+    /// octave.
+    fn name(&self, note: MidiNote, _sharp: bool) -> String {
+        match self.degree(note) {
+            Some((degree, octave)) => format!("{}_{}", degree, octave),
+            None => String::new(),
+        }
+    }
+
+    /// Color the notes by how close they are to the notes of 12-EDO.
+    fn color(&self, note: MidiNote, _sharp: bool) -> RGB8 {
+        let (degree, octave) = self.degree(note).unwrap_or((0, 0));
+        cents_color(self.degrees[degree], degree == 0, degree == 0 && octave == 4)
+    }
+
+    fn middle_c(&self) -> MidiNote {
+        self.encoding.encode(0, self.size()).expect("Encoding checked for middle C")
+    }
+
+    fn cents(&self, note: MidiNote) -> Option<f64> {
+        let steps = self.encoding.decode(note, self.size())?;
+        Some(self.step_cents(steps))
+    }
+
+    fn steps(&self, note: MidiNote) -> Option<isize> {
+        self.encoding.decode(note, self.size())
+    }
+
+    fn period_steps(&self) -> Option<usize> {
+        Some(self.size())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ScalaTuning;
+    use crate::tuning::{
+        Interval, IntervalDirection, IntervalStep, MidiNote, Reference, Tuning, EDO12, EDO53,
+        MIDDLE_C_HZ,
+    };
+
+    #[test]
+    fn scala_edo31() {
+        let scale = ScalaTuning::parse(include_str!("../../layouts/31-edo.scl")).unwrap();
+        assert_eq!(scale.description(), "31 equal divisions of 2/1");
+        assert_eq!(scale.size(), 31);
+        assert!((scale.period() - 1200.0).abs() < 1e-9);
+
+        // These should match the EDO31 tables.
+        assert_eq!(scale.get_steps(IntervalStep::MajorSecond), 5);
+        assert_eq!(scale.get_steps(IntervalStep::MajorThird), 10);
+        assert_eq!(scale.get_steps(IntervalStep::PerfectFifth), 18);
+
+        let c4 = scale.middle_c();
+        let up = Interval::new(IntervalStep::PerfectFifth, IntervalDirection::Up);
+        let down = Interval::new(IntervalStep::PerfectFourth, IntervalDirection::Down);
+        assert_eq!(scale.interval(c4, up), Some(MidiNote { channel: 4, note: 78 }));
+        assert_eq!(scale.interval(c4, down), Some(MidiNote { channel: 3, note: 78 }));
+        assert_eq!(scale.name(MidiNote { channel: 3, note: 78 }, true), "18_3");
+    }
+
+    #[test]
+    fn scala_ratios() {
+        let text = "! just.scl\n!\nJust major\n 7\n!\n9/8\n5/4\n4/3\n3/2 fifth\n5/3\n15/8\n2\n";
+        let scale = ScalaTuning::parse(text).unwrap().single_channel();
+        assert_eq!(scale.size(), 7);
+        assert_eq!(scale.get_steps(IntervalStep::MajorThird), 2);
+        assert_eq!(scale.get_steps(IntervalStep::PerfectFifth), 4);
+        assert_eq!(scale.name(MidiNote { channel: 1, note: 64 }, true), "4_4");
+
+        assert!(ScalaTuning::parse("Short\n 3\n9/8\n5/4\n").is_err());
+        assert!(ScalaTuning::parse("Bad\n 1\n0/1\n").is_err());
+    }
+
+    #[test]
+    fn scala_edo_export() {
+        let scale = ScalaTuning::parse(&EDO53.scl()).unwrap();
+        assert_eq!(scale.size(), 53);
+        assert_eq!(scale.get_steps(IntervalStep::PerfectFifth), 31);
+
+        let kbm = EDO53.kbm(&Reference::middle_c(&EDO53, MIDDLE_C_HZ)).unwrap();
+        let values: Vec<&str> = kbm.lines().filter(|l| !l.starts_with('!')).collect();
+        assert_eq!(&values[..7], ["53", "1", "53", "1", "1", "261.625565", "53"]);
+        assert_eq!(values.len(), 7 + 53);
+
+        let a4 = Reference::named(&EDO12, "A4", 432.0).unwrap();
+        let kbm = EDO12.kbm(&a4).unwrap();
+        let values: Vec<&str> = kbm.lines().filter(|l| !l.starts_with('!')).collect();
+        assert_eq!(&values[..7], ["12", "0", "127", "60", "60", "256.868737", "12"]);
+
+        // A reference from another tuning may not be a note of this one.
+        assert!(EDO53.kbm(&a4).is_err());
+    }
+}
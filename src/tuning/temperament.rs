@@ -0,0 +1,337 @@
+//! Rank-2 temperaments.
+//!
+//! A rank-2 temperament is defined by two intervals, a period (usually the
+//! octave), and a generator.  Every note is some number of periods plus some
+//! number of generators.  Meantone, for example, has an octave period, and a
+//! slightly flat fifth as the generator.
+//!
+//! The midi mapping gives each position along the chain of generators its own
+//! note number, and each period its own channel, so a layout can be built
+//! before deciding the exact size of the generator.  To the encoding, each
+//! period is a block of 128 steps, one for each position along the chain.
+
+use anyhow::{bail, Result};
+
+use crate::lumatone::RGB8;
+
+use super::{
+    cents_color, name_color, Blocks, Encoding, Interval, IntervalSize, IntervalStep, MidiEncoding, MidiNote,
+    Tuning,
+};
+
+/// The number of positions along the chain of generators in each period.
+const CHAIN_SIZE: isize = 128;
+
+/// The position along the chain of generators of the first note of each
+/// period.  This puts middle C in the middle of the notes.
+const CHAIN_BIAS: isize = 64;
+
+/// The default encoding, with the period starting at middle C on channel 4.
+const CHAIN_ENCODING: Encoding = Encoding::Blocks(Blocks { channel: 1, middle: 3 * 128 + 64 });
+
+/// A rank-2 temperament, defined by a period and generator.
+#[derive(Debug, Clone)]
+pub struct Temperament {
+    /// The size of the period, in cents.
+    period: f64,
+    /// The size of the generator, in cents.
+    generator: f64,
+    /// How the notes are named.
+    names: ChainNames,
+    /// How the periods and positions along the chain are sent as midi notes.
+    encoding: Encoding,
+}
+
+/// How a temperament names its notes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ChainNames {
+    /// The generator is a fifth, and notes use letter names.
+    Fifths,
+    /// The generator is a fourth, and notes use letter names, with the chain
+    /// running backwards.
+    Fourths,
+    /// Notes are named by their position along the chain.
+    Chain,
+}
+
+impl Temperament {
+    /// Construct a temperament from the size of its period and generator, in
+    /// cents.  When the period is an octave, and the generator is some kind of
+    /// fifth or fourth, the notes get letter names, otherwise they are named by
+    /// their position along the generator chain.  Fails if the period isn't
+    /// larger than the unison.
+    pub fn new(period: f64, generator: f64) -> Result<Temperament> {
+        if !period.is_finite() || period <= 0.0 {
+            bail!("Period of {} cents must be larger than the unison", period);
+        }
+        if !generator.is_finite() {
+            bail!("Invalid generator of {} cents", generator);
+        }
+        Ok(Temperament::build(period, generator))
+    }
+
+    /// Construct a temperament, once the sizes have been checked.
+    fn build(period: f64, generator: f64) -> Temperament {
+        let names = if (period - 1200.0).abs() > 1e-6 {
+            ChainNames::Chain
+        } else {
+            let reduced = generator.rem_euclid(1200.0);
+            if (600.0..800.0).contains(&reduced) {
+                ChainNames::Fifths
+            } else if (400.0..600.0).contains(&reduced) {
+                ChainNames::Fourths
+            } else {
+                ChainNames::Chain
+            }
+        };
+        Temperament { period, generator, names, encoding: CHAIN_ENCODING }
+    }
+
+    /// Quarter-comma meantone, with pure major thirds.
+    pub fn meantone() -> Temperament {
+        Temperament::build(1200.0, 696.578)
+    }
+
+    /// Porcupine, where three generators make a fourth.
+    pub fn porcupine() -> Temperament {
+        Temperament::build(1200.0, 163.950)
+    }
+
+    /// Magic, where five major thirds make a twelfth.
+    pub fn magic() -> Temperament {
+        Temperament::build(1200.0, 380.499)
+    }
+
+    /// Mavila, an anti-diatonic temperament with a very flat fifth.
+    pub fn mavila() -> Temperament {
+        Temperament::build(1200.0, 679.806)
+    }
+
+    /// Send the notes of this temperament with a different midi encoding.
+    /// Each period is a block of 128 steps, with middle C at step 0.  Fails
+    /// if the encoding can't hold middle C.
+    pub fn with_encoding<E: Into<Encoding>>(self, encoding: E) -> Result<Temperament> {
+        let encoding = encoding.into().check(CHAIN_SIZE as usize)?;
+        Ok(Temperament { encoding, ..self })
+    }
+
+    /// The size of the period, in cents.
+    pub fn period(&self) -> f64 {
+        self.period
+    }
+
+    /// The size of the generator, in cents.
+    pub fn generator(&self) -> f64 {
+        self.generator
+    }
+
+    /// Return the interval as a number of periods and a number of generators.
+    /// Raw steps are taken as a number of generators.
+    fn vector(&self, size: IntervalSize) -> Option<(isize, isize)> {
+        if let IntervalSize::Steps(gens) = size {
+            return Some((0, gens));
+        }
+        let (fifths, octaves) = size.vector()?;
+        let vector = match self.names {
+            ChainNames::Fifths => (octaves, fifths),
+            ChainNames::Fourths => (octaves + fifths, -fifths),
+            ChainNames::Chain => {
+                // Without a fifth, pick the shortest chain that comes closest to
+                // the just interval.
+                let target = size.just_cents()?;
+                (-12..=12_isize)
+                    .map(|gens| {
+                        let rest = target - gens as f64 * self.generator;
+                        ((rest / self.period).round() as isize, gens)
+                    })
+                    .min_by(|a, b| {
+                        let err_a = (self.pitch(*a) - target).abs();
+                        let err_b = (self.pitch(*b) - target).abs();
+                        err_a.total_cmp(&err_b).then(a.1.abs().cmp(&b.1.abs()))
+                    })
+                    .unwrap()
+            }
+        };
+        Some(vector)
+    }
+
+    /// The pitch, in cents, of the given number of periods and generators.
+    fn pitch(&self, (periods, gens): (isize, isize)) -> f64 {
+        periods as f64 * self.period + gens as f64 * self.generator
+    }
+
+    /// The period within the channel that a generator position falls in.
+    fn chain_period(&self, gens: isize) -> isize {
+        (gens as f64 * self.generator / self.period).floor() as isize
+    }
+
+    /// Decode a note into periods and generators from middle C.
+    fn decode(&self, note: MidiNote) -> Option<(isize, isize)> {
+        let steps = self.encoding.decode(note, CHAIN_SIZE as usize)? + CHAIN_BIAS;
+        let gens = steps.rem_euclid(CHAIN_SIZE) - CHAIN_BIAS;
+        let periods = steps.div_euclid(CHAIN_SIZE) - self.chain_period(gens);
+        Some((periods, gens))
+    }
+
+    /// Encode periods and generators from middle C into a note.  The chain
+    /// position gives the step within the block, and the block is the period
+    /// that the pitch falls in.
+    fn encode(&self, (periods, gens): (isize, isize)) -> Option<MidiNote> {
+        if !(-CHAIN_BIAS..CHAIN_SIZE - CHAIN_BIAS).contains(&gens) {
+            return None;
+        }
+        let block = periods + self.chain_period(gens);
+        self.encoding.encode(block * CHAIN_SIZE + gens, CHAIN_SIZE as usize)
+    }
+
+    /// Return the letter name, and the octave number, for a note that has been
+    /// decoded as fifths and octaves from middle C.
+    fn letter_name(fifths: isize, octaves: isize) -> String {
+        let letter = ["F", "C", "G", "D", "A", "E", "B"][(fifths + 1).rem_euclid(7) as usize];
+        let sharps = (fifths + 1).div_euclid(7);
+        let accidental = if sharps >= 0 {
+            format!("{}{}", "𝄪".repeat(sharps as usize / 2), "♯".repeat(sharps as usize % 2))
+        } else {
+            let flats = sharps.unsigned_abs();
+            format!("{}{}", "𝄫".repeat(flats / 2), "♭".repeat(flats % 2))
+        };
+
+        // A fifth is four diatonic steps, and an octave is seven.  This makes
+        // the octave number follow the letter, so B♯3 is near C4.
+        let octave = 4 + (4 * fifths + 7 * octaves).div_euclid(7);
+        format!("{}{}{}", letter, accidental, octave)
+    }
+}
+
+impl Tuning for Temperament {
+    /// Temperaments don't have a single step size.  This returns the number of
+    /// generators in the interval.
+    fn get_steps(&self, interval: IntervalStep) -> isize {
+        self.vector(interval.into()).unwrap().1
+    }
+
+    fn interval_steps(&self, size: IntervalSize) -> Option<isize> {
+        Some(self.vector(size)?.1)
+    }
+
+    fn interval(&self, note: MidiNote, interval: Interval) -> Option<MidiNote> {
+        let (periods, gens) = self.decode(note)?;
+        let (dp, dg) = self.vector(interval.size())?;
+        if interval.is_up() {
+            self.encode((periods + dp, gens + dg))
+        } else {
+            self.encode((periods - dp, gens - dg))
+        }
+    }
+
+    /// With letter names, the sharp hint isn't needed, as each position along
+    /// the chain has its own name.  Otherwise, the name is the chain position,
+    /// followed by the channel.
+    fn name(&self, note: MidiNote, _sharp: bool) -> String {
+        let (periods, gens) = match self.decode(note) {
+            Some(pos) => pos,
+            None => return String::new(),
+        };
+        match self.names {
+            ChainNames::Fifths => Temperament::letter_name(gens, periods),
+            ChainNames::Fourths => Temperament::letter_name(-gens, periods + gens),
+            ChainNames::Chain => format!("{}_{}", gens, note.channel),
+        }
+    }
+
+    fn color(&self, note: MidiNote, sharp: bool) -> RGB8 {
+        match self.names {
+            ChainNames::Fifths | ChainNames::Fourths => name_color(&self.name(note, sharp)),
+            ChainNames::Chain => {
+                let (periods, gens) = self.decode(note).unwrap_or((0, 0));
+                let reduced = self.pitch((periods, gens)).rem_euclid(self.period);
+                cents_color(reduced, gens == 0, gens == 0 && periods == 0)
+            }
+        }
+    }
+
+    fn middle_c(&self) -> MidiNote {
+        self.encoding.encode(0, CHAIN_SIZE as usize).expect("Encoding checked for middle C")
+    }
+
+    fn cents(&self, note: MidiNote) -> Option<f64> {
+        Some(self.pitch(self.decode(note)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Temperament;
+    use crate::tuning::{Interval, IntervalDirection, IntervalStep, MidiNote, Sequential, Tuning};
+
+    #[test]
+    fn temperament_meantone() {
+        let mt = Temperament::meantone();
+        let c4 = mt.middle_c();
+        assert_eq!(mt.name(c4, true), "C4");
+
+        let up = |step| Interval::new(step, IntervalDirection::Up);
+        let down = |step| Interval::new(step, IntervalDirection::Down);
+
+        let e4 = mt.interval(c4, up(IntervalStep::MajorThird)).unwrap();
+        assert_eq!(e4, MidiNote { channel: 4, note: 68 });
+        assert_eq!(mt.name(e4, true), "E4");
+        assert!((mt.cents(e4).unwrap() - 386.312).abs() < 1e-3);
+
+        // Enharmonics are different notes.
+        let cs4 = mt.interval(c4, up(IntervalStep::AugUnison)).unwrap();
+        let db4 = mt.interval(c4, up(IntervalStep::MinorSecond)).unwrap();
+        assert_eq!(mt.name(cs4, true), "C♯4");
+        assert_eq!(mt.name(db4, true), "D♭4");
+        assert!(mt.cents(cs4).unwrap() < mt.cents(db4).unwrap());
+
+        let f3 = mt.interval(c4, down(IntervalStep::PerfectFifth)).unwrap();
+        assert_eq!(mt.name(f3, true), "F3");
+        assert_eq!(f3.channel, 3);
+        let b3 = mt.interval(c4, down(IntervalStep::MinorSecond)).unwrap();
+        assert_eq!(mt.name(b3, true), "B3");
+        let bs3 = mt.interval(b3, up(IntervalStep::AugUnison)).unwrap();
+        assert_eq!(mt.name(bs3, true), "B♯3");
+    }
+
+    #[test]
+    fn temperament_chain() {
+        // A fourth generator still gets letter names.
+        let fourths = Temperament::new(1200.0, 503.422).unwrap();
+        let up = Interval::new(IntervalStep::PerfectFifth, IntervalDirection::Up);
+        let g4 = fourths.interval(fourths.middle_c(), up).unwrap();
+        assert_eq!(fourths.name(g4, true), "G4");
+
+        let porcupine = Temperament::porcupine();
+        assert_eq!(porcupine.get_steps(IntervalStep::PerfectFourth), 3);
+        let up = Interval::new(IntervalStep::PerfectFourth, IntervalDirection::Up);
+        let p4 = porcupine.interval(porcupine.middle_c(), up).unwrap();
+        assert_eq!(p4, MidiNote { channel: 4, note: 67 });
+        assert_eq!(porcupine.name(p4, true), "3_4");
+
+        assert!(Temperament::new(0.0, 700.0).is_err());
+        assert!(Temperament::new(1200.0, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn temperament_encoding() {
+        // The periods run from channel 1 to 16, and channel 0 is never used.
+        let mt = Temperament::meantone();
+        assert_eq!(mt.encode((-3, 0)), Some(MidiNote { channel: 1, note: 64 }));
+        assert_eq!(mt.encode((-4, 0)), None);
+        assert_eq!(mt.encode((12, 0)), Some(MidiNote { channel: 16, note: 64 }));
+        assert_eq!(mt.decode(MidiNote { channel: 0, note: 64 }), None);
+
+        let single = mt.with_encoding(Sequential { channel: 1, middle: 60 }).unwrap();
+        assert_eq!(single.middle_c(), MidiNote { channel: 1, note: 60 });
+        let up = Interval::new(IntervalStep::PerfectFifth, IntervalDirection::Up);
+        let g4 = single.interval(single.middle_c(), up).unwrap();
+        assert_eq!(g4, MidiNote { channel: 1, note: 61 });
+        assert_eq!(single.name(g4, true), "G4");
+        // A period up doesn't fit on a single channel.
+        let up = Interval::new(IntervalStep::Octave, IntervalDirection::Up);
+        assert_eq!(single.interval(single.middle_c(), up), None);
+
+        assert!(Temperament::meantone().with_encoding(Sequential { channel: 0, middle: 60 }).is_err());
+    }
+}